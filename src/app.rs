@@ -1,12 +1,37 @@
 use anyhow::{Context, Result};
 use egui::{Color32, Grid, RichText};
 use egui_file_dialog::{DialogState, FileDialog};
-use image::Rgba;
 use macroquad::prelude::*;
-use std::path::PathBuf;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
-use crate::{image::Image, map::Map};
+use crate::loader::{Image, process_all};
+use thermal_maps::{
+    Bar, Filter, ImageData,
+    calibration::detect_bar,
+    dji::Parameters,
+    export,
+    flir::CameraInfo,
+    image::list_images,
+    isotherm::{self, Fill, Isotherm},
+    map::Lookup,
+    ocr::{Reading, Recognizer, TEMPLATES_DIR, save_sample},
+    palette::{PALETTES_DIR, Palette},
+    project::Project,
+    radiometric::{Camera, KELVIN, Scale},
+    render::Layer,
+    roi::{Roi, Shape, ShapeKind},
+    spot::Spot,
+    stats::{Histogram, Stats},
+};
 
+// Seconds without edits before the project file is written
+const SAVE_DELAY: f64 = 1.0;
+
+// There is only ever one state, so the size difference does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug)]
 pub enum AppState {
     SelectFolder(SelectFolderData),
@@ -52,35 +77,102 @@ impl SelectFolderData {
 
 #[derive(Debug)]
 pub struct BrowseData {
+    folder: PathBuf,
+    project_dirty: bool,
+    // The project file could not be read, so it is never written over
+    keep_project: bool,
+    // When the project last changed, while it is not saved yet
+    last_edit: Option<f64>,
     images: Vec<Image>,
     loaded: bool,
     images_height: f32,
     max_width: f32,
     scroll: f32,
     selected_image: Option<usize>,
-    hover: Option<f32>,
+    hover: Option<(f32, f32)>,
     save_dialog: egui_file_dialog::FileDialog,
+    folder_bar: Option<Bar>,
+    tool: Tool,
+    drag: Option<((u32, u32), (u32, u32))>,
+    polygon: Vec<(u32, u32)>,
+    moving_spot: Option<usize>,
+    profile: Option<((u32, u32), (u32, u32))>,
+    histogram_roi: Option<usize>,
+    // A text field has focus, so shortcuts are left to it
+    typing: bool,
+    hotspots: Hotspots,
+    recognizer: Arc<Recognizer>,
+    palettes: Vec<Palette>,
+}
+
+#[derive(Debug)]
+struct Hotspots {
+    extremes: bool,
+    count: usize,
+    threshold: Option<f32>,
+    spacing: u32,
+    found: Vec<((u32, u32), f32)>,
+}
+
+impl Default for Hotspots {
+    fn default() -> Self {
+        Hotspots {
+            extremes: false,
+            count: 5,
+            threshold: None,
+            spacing: 10,
+            found: Vec::new(),
+        }
+    }
+}
+
+enum HistoryAction {
+    Undo,
+    Redo,
+}
+
+enum FolderAction {
+    ApplyCalibration(Box<ImageData>),
+    ExtractAll,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    None,
+    Calibrate,
+    Roi(ShapeKind),
+    Profile,
 }
 
 impl BrowseData {
     pub fn new(path: PathBuf) -> Result<Self> {
-        let mut images = Vec::new();
-        for entry in std::fs::read_dir(path.clone()).expect("Failed to read selected directory") {
-            if let Ok(entry) = entry {
-                if let Some(ext) = entry.path().extension() {
-                    if ext == "png" || ext == "jpg" || ext == "jpeg" || ext == "bmp" {
-                        images.push(
-                            Image::new(entry.path()).context(format!(
-                                "Failed to create image from {}",
-                                path.display()
-                            ))?,
-                        );
-                    }
-                }
+        let (mut project, keep_project) = match Project::load(&path) {
+            Ok(project) => (project.unwrap_or_default(), false),
+            Err(e) => {
+                eprintln!("{e:#}");
+                eprintln!("Edits to this folder will not be saved");
+                (Project::default(), true)
             }
+        };
+
+        let mut images = Vec::new();
+        for image_path in list_images(&path)? {
+            let name = image_path
+                .file_name()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string();
+            let mut image = Image::new(image_path)
+                .context(format!("Failed to create image from {}", path.display()))?;
+            image.saved = project.images.remove(&name);
+            images.push(image);
         }
 
         Ok(BrowseData {
+            folder: path,
+            project_dirty: false,
+            keep_project,
+            last_edit: None,
             images,
             loaded: false,
             images_height: 0.0,
@@ -88,10 +180,287 @@ impl BrowseData {
             scroll: 0.0,
             selected_image: None,
             hover: None,
-            save_dialog: FileDialog::new().default_pos([10.0, 10.0]),
+            save_dialog: FileDialog::new()
+                .default_pos([10.0, 10.0])
+                .add_save_extension("PNG image", "png")
+                .add_save_extension("JPEG image", "jpg")
+                .add_save_extension("BMP image", "bmp")
+                .add_save_extension("Temperature grid", "csv")
+                .add_save_extension("Temperature summary", "summary.csv")
+                .add_save_extension("Temperature TIFF", "tif")
+                .add_save_extension("NumPy array", "npy")
+                .default_save_extension("PNG image"),
+            folder_bar: project.folder_bar,
+            tool: Tool::None,
+            drag: None,
+            polygon: Vec::new(),
+            moving_spot: None,
+            profile: None,
+            histogram_roi: None,
+            typing: false,
+            hotspots: Hotspots::default(),
+            recognizer: Arc::new(Recognizer::load(Path::new(TEMPLATES_DIR))),
+            palettes: Palette::library(Path::new(PALETTES_DIR)),
         })
     }
 
+    // Writes the edits of every image to the project file in the folder
+    fn save_project(&mut self) {
+        if self.keep_project {
+            return;
+        }
+        let mut project = Project::new(self.folder_bar);
+        for image in &mut self.images {
+            if let Some(p) = image.project() {
+                let name = image.path.file_name().unwrap_or_default().to_string_lossy();
+                project.images.insert(name.to_string(), p);
+            }
+        }
+        if let Err(e) = project.save(&self.folder) {
+            eprintln!("{e:#}");
+        }
+    }
+
+    fn view_rect(&self, t: &Texture2D) -> Rect {
+        let (width, height) = scale_texture(t.clone(), self.max_width);
+        Rect::new(self.max_width + 10.0, 0.0, width, height)
+    }
+
+    fn screen_to_pixel(&self, t: &Texture2D, pos: (f32, f32)) -> Option<(u32, u32)> {
+        let rect = self.view_rect(t);
+        if !rect.contains(Vec2::new(pos.0, pos.1)) {
+            return None;
+        }
+        Some(self.screen_to_pixel_clamped(t, pos))
+    }
+
+    fn screen_to_pixel_clamped(&self, t: &Texture2D, pos: (f32, f32)) -> (u32, u32) {
+        let rect = self.view_rect(t);
+        let x = (pos.0 - rect.x) * (t.width() / rect.w);
+        let y = (pos.1 - rect.y) * (t.height() / rect.h);
+        (
+            x.clamp(0.0, t.width() - 1.0) as u32,
+            y.clamp(0.0, t.height() - 1.0) as u32,
+        )
+    }
+
+    fn pixel_to_screen(&self, t: &Texture2D, p: (f32, f32)) -> Vec2 {
+        self.image_to_screen(t, (p.0 + 0.5, p.1 + 0.5))
+    }
+
+    // Like `pixel_to_screen`, but for a point between pixels rather than a pixel
+    fn image_to_screen(&self, t: &Texture2D, p: (f32, f32)) -> Vec2 {
+        let rect = self.view_rect(t);
+        Vec2::new(
+            rect.x + p.0 * (rect.w / t.width()),
+            rect.y + p.1 * (rect.h / t.height()),
+        )
+    }
+
+    fn update_calibration_drag(&mut self) {
+        let Some(selected) = self.selected_image else {
+            return;
+        };
+        let Some(t) = self.images[selected].texture.clone() else {
+            return;
+        };
+
+        let mouse_pos = mouse_position();
+        if is_mouse_button_pressed(MouseButton::Left)
+            && let Some(p) = self.screen_to_pixel(&t, mouse_pos)
+        {
+            self.drag = Some((p, p));
+        }
+
+        if let Some((start, _)) = self.drag {
+            let end = self.screen_to_pixel_clamped(&t, mouse_pos);
+            self.drag = Some((start, end));
+
+            if is_mouse_button_released(MouseButton::Left) {
+                self.drag = None;
+                if start == end {
+                    return;
+                }
+
+                let bar = if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+                    Bar::from_rect(start, end)
+                } else {
+                    let bar = Bar::new(start, end, 1);
+                    let hot_first = if bar.is_vertical() {
+                        start.1 < end.1
+                    } else {
+                        start.0 > end.0
+                    };
+                    if hot_first {
+                        bar
+                    } else {
+                        Bar::new(end, start, 1)
+                    }
+                };
+
+                if let Some(d) = self.images[selected].data.lock().unwrap().as_mut() {
+                    d.bar = Some(bar);
+                }
+                self.tool = Tool::None;
+            }
+        }
+    }
+
+    // Rectangles and ellipses are dragged, polygons are clicked point by point
+    // and closed with Enter or by clicking their first point again
+    fn update_roi_tool(&mut self, kind: ShapeKind) {
+        let Some(selected) = self.selected_image else {
+            return;
+        };
+        let Some(t) = self.images[selected].texture.clone() else {
+            return;
+        };
+        if is_key_pressed(KeyCode::Escape) {
+            self.drag = None;
+            self.polygon.clear();
+            self.tool = Tool::None;
+            return;
+        }
+
+        let mouse_pos = mouse_position();
+        let shape = match kind {
+            ShapeKind::Polygon => {
+                let mut close = is_key_pressed(KeyCode::Enter);
+                if is_mouse_button_pressed(MouseButton::Left)
+                    && let Some(p) = self.screen_to_pixel(&t, mouse_pos)
+                {
+                    match self.polygon.first() {
+                        Some(&first)
+                            if self
+                                .pixel_to_screen(&t, (first.0 as f32, first.1 as f32))
+                                .distance(mouse_pos.into())
+                                <= 6.0 =>
+                        {
+                            close = true
+                        }
+                        _ => self.polygon.push(p),
+                    }
+                }
+                if !close {
+                    return;
+                }
+                let points = std::mem::take(&mut self.polygon);
+                if points.len() < 3 {
+                    return;
+                }
+                Shape::polygon(&points)
+            }
+            ShapeKind::Rectangle | ShapeKind::Ellipse => {
+                if is_mouse_button_pressed(MouseButton::Left)
+                    && let Some(p) = self.screen_to_pixel(&t, mouse_pos)
+                {
+                    self.drag = Some((p, p));
+                }
+                let Some((start, _)) = self.drag else {
+                    return;
+                };
+                let end = self.screen_to_pixel_clamped(&t, mouse_pos);
+                self.drag = Some((start, end));
+                if !is_mouse_button_released(MouseButton::Left) {
+                    return;
+                }
+                self.drag = None;
+                if start == end {
+                    return;
+                }
+                if kind == ShapeKind::Rectangle {
+                    Shape::rectangle(start, end)
+                } else {
+                    Shape::ellipse(start, end)
+                }
+            }
+        };
+
+        if let Some(d) = self.images[selected].data.lock().unwrap().as_mut() {
+            let name = Roi::next_name(&d.rois, kind);
+            d.rois.push(Roi { name, shape });
+        }
+    }
+
+    fn update_profile_drag(&mut self) {
+        let Some(selected) = self.selected_image else {
+            return;
+        };
+        let Some(t) = self.images[selected].texture.clone() else {
+            return;
+        };
+        if is_key_pressed(KeyCode::Escape) {
+            self.drag = None;
+            self.tool = Tool::None;
+            return;
+        }
+
+        let mouse_pos = mouse_position();
+        if is_mouse_button_pressed(MouseButton::Left)
+            && let Some(p) = self.screen_to_pixel(&t, mouse_pos)
+        {
+            self.drag = Some((p, p));
+        }
+        if let Some((start, _)) = self.drag {
+            let end = self.screen_to_pixel_clamped(&t, mouse_pos);
+            self.drag = Some((start, end));
+            if is_mouse_button_released(MouseButton::Left) {
+                self.drag = None;
+                if start != end {
+                    self.profile = Some((start, end));
+                }
+            }
+        }
+    }
+
+    // Right click adds a spot or removes the one under the mouse, dragging
+    // one with the left button moves it
+    fn update_spots(&mut self) {
+        let Some(selected) = self.selected_image else {
+            return;
+        };
+        let Some(t) = self.images[selected].texture.clone() else {
+            return;
+        };
+        let mut data = self.images[selected].data.lock().unwrap();
+        let Some(d) = data.as_mut() else {
+            return;
+        };
+
+        let mouse_pos = mouse_position();
+        let under_mouse = d.spots.iter().position(|s| {
+            self.pixel_to_screen(&t, (s.position.0 as f32, s.position.1 as f32))
+                .distance(mouse_pos.into())
+                <= 6.0
+        });
+
+        if is_mouse_button_pressed(MouseButton::Right) {
+            match under_mouse {
+                Some(i) => {
+                    d.spots.remove(i);
+                }
+                None => {
+                    if let Some(position) = self.screen_to_pixel(&t, mouse_pos) {
+                        let name = Spot::next_name(&d.spots);
+                        d.spots.push(Spot { name, position });
+                    }
+                }
+            }
+        }
+
+        if is_mouse_button_pressed(MouseButton::Left) {
+            self.moving_spot = under_mouse;
+        }
+        if let Some(i) = self.moving_spot
+            && let Some(spot) = d.spots.get_mut(i)
+        {
+            spot.position = self.screen_to_pixel_clamped(&t, mouse_pos);
+        }
+        if is_mouse_button_released(MouseButton::Left) {
+            self.moving_spot = None;
+        }
+    }
+
     pub async fn update(&mut self) -> Result<Option<AppState>> {
         if self.save_dialog.state() != DialogState::Open {
             // Update scrolling
@@ -110,8 +479,32 @@ impl BrowseData {
                 }
             }
 
+            let ctrl = is_key_down(KeyCode::LeftControl) || is_key_down(KeyCode::RightControl);
+            let shift = is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift);
+            if ctrl
+                && !self.typing
+                && is_key_pressed(KeyCode::Z)
+                && let Some(selected) = self.selected_image
+            {
+                if shift {
+                    self.images[selected].redo(self.folder_bar);
+                } else {
+                    self.images[selected].undo(self.folder_bar);
+                }
+                self.project_dirty = true;
+            }
+
             // Handle mouse selection
-            if is_mouse_button_pressed(MouseButton::Left) {
+            if self.tool == Tool::Calibrate {
+                self.update_calibration_drag();
+            } else if let Tool::Roi(kind) = self.tool {
+                self.update_roi_tool(kind);
+            } else if self.tool == Tool::Profile {
+                self.update_profile_drag();
+            } else {
+                self.update_spots();
+            }
+            if self.tool == Tool::None && is_mouse_button_pressed(MouseButton::Left) {
                 let mouse_pos = mouse_position();
                 let mut y = self.scroll;
                 for (i, image) in self.images.iter().enumerate() {
@@ -121,6 +514,11 @@ impl BrowseData {
                             && mouse_pos.1 >= y
                             && mouse_pos.1 <= y + t.height() / 2.0
                         {
+                            if self.selected_image != Some(i) {
+                                self.profile = None;
+                                self.histogram_roi = None;
+                                self.hotspots.found.clear();
+                            }
                             self.selected_image = Some(i);
                             break;
                         }
@@ -131,121 +529,114 @@ impl BrowseData {
                     }
                 }
             }
-
-            // Add a mark on the image if right click
-            if is_mouse_button_pressed(MouseButton::Right) {
-                if let Some(selected) = self.selected_image {
-                    if let Some(image) = self.images.get_mut(selected) {
-                        // Get coordinates of the mouse click on the image
-                        let mouse_pos = mouse_position();
-                        let image_pos = Vec2::new(self.max_width + 10.0, 0.0);
-                        let image_width = screen_width() - self.max_width - 10.0 - 175.0 - 10.0;
-                        let image_ratio = image.texture.as_ref().unwrap().width() as f32
-                            / image.texture.as_ref().unwrap().height() as f32;
-                        let image_height = image_width / image_ratio;
-                        if mouse_pos.0 >= image_pos.x
-                            && mouse_pos.0 <= image_pos.x + image_width
-                            && mouse_pos.1 >= image_pos.y
-                            && mouse_pos.1 <= image_pos.y + image_height
-                        {
-                            let pixel_x = (mouse_pos.0 - image_pos.x)
-                                * (image.texture.as_ref().unwrap().width()
-                                    / (screen_width() - self.max_width - 10.0 - 175.0 - 10.0));
-                            let pixel_y = (mouse_pos.1 - image_pos.y)
-                                * (image.texture.as_ref().unwrap().height()
-                                    / (screen_height() - self.scroll));
-                            if let Some(d) = image.data.lock().unwrap().as_mut() {
-                                d.image.put_pixel(
-                                    pixel_x as u32,
-                                    pixel_y as u32,
-                                    Rgba([255, 0, 0, 255]),
-                                );
-                                image.texture = Some(Texture2D::from_rgba8(
-                                    d.image.width() as u16,
-                                    d.image.height() as u16,
-                                    d.image.as_raw(),
-                                ));
-                            }
-                        }
-                    }
-                }
-            }
         }
 
-        // Update images the have an image loaded but not the texture
+        // Render images that were loaded or changed since the last frame. Only
+        // the selected image is edited by hand, and a drag only becomes a
+        // history entry once the mouse is released.
+        let dragging = is_mouse_button_down(MouseButton::Left);
         let mut not_loaded = false;
-        for image in self.images.iter_mut() {
-            let is_loading = *image.is_loading.lock().unwrap();
-            if is_loading {
+        for (i, image) in self.images.iter_mut().enumerate() {
+            if *image.is_loading.lock().unwrap() {
                 not_loaded = true;
+            } else {
+                image.update_texture();
+                if !dragging && self.selected_image == Some(i) && image.track_changes() {
+                    self.project_dirty = true;
+                }
             }
-            if !is_loading
-                && let Some(d) = image.data.lock().unwrap().as_ref()
-                && image.texture.is_none()
-            {
-                image.texture = Some(Texture2D::from_rgba8(
-                    d.image.width() as u16,
-                    d.image.height() as u16,
-                    d.image.as_raw(),
-                ));
-            }
+        }
+        // The project file is written whole, so wait for a pause in the edits
+        // and for background work to finish
+        if self.project_dirty {
+            self.last_edit = Some(get_time());
+            self.project_dirty = false;
+        }
+        if let Some(edited) = self.last_edit
+            && !dragging
+            && !not_loaded
+            && get_time() - edited >= SAVE_DELAY
+        {
+            self.save_project();
+            self.last_edit = None;
         }
         if !not_loaded {
             self.loaded = true;
         }
 
         // Update temperature based on mouse pos from the image
-        if let Some(selected) = self.selected_image {
-            if let Some(t) = &self.images[selected].texture
-                && let Some(d) = self.images[selected].data.lock().unwrap().as_ref()
-            {
-                let mouse_pos = mouse_position();
-                let (width, height) = scale_texture(t.clone(), self.max_width);
-                let x_offset = self.max_width + 10.0;
-                let y_offset = 0.0;
-
-                // Check if mouse is hovering over the image
-                if mouse_pos.0 >= x_offset
-                    && mouse_pos.0 <= x_offset + width
-                    && mouse_pos.1 >= y_offset
-                    && mouse_pos.1 <= y_offset + height
-                {
-                    let pixel_x = (mouse_pos.0 - x_offset) * (t.width() / width);
-                    let pixel_y = (mouse_pos.1 - y_offset) * (t.height() / height);
-
-                    if pixel_x < d.image.width() as f32 && pixel_y < d.image.height() as f32 {
-                        let pixel = d.raw_image.get_pixel(pixel_x as u32, pixel_y as u32);
-                        let rgb = [pixel[0], pixel[1], pixel[2]];
-                        self.hover = d.color_temp.get_closest_by(|color| {
-                            let d = |a: u8, b: u8| (a as f32 - b as f32).powi(2);
-                            d(color[0], rgb[0]) + d(color[1], rgb[1]) + d(color[2], rgb[2])
-                        });
-                    }
-                } else {
-                    self.hover = None;
-                }
+        if let Some(selected) = self.selected_image
+            && let Some(t) = &self.images[selected].texture
+            && let Some(d) = self.images[selected].data.lock().unwrap().as_ref()
+        {
+            // Check if mouse is hovering over the image
+            if let Some((pixel_x, pixel_y)) = self.screen_to_pixel(t, mouse_position()) {
+                let pixel = d.raw_image.get_pixel(pixel_x, pixel_y);
+                self.hover = d.temperature_at(pixel_x, pixel_y).map(|temp| {
+                    let distance = d
+                        .temperature_of([pixel[0], pixel[1], pixel[2]])
+                        .map_or(0.0, |(_, distance)| distance);
+                    (temp, distance)
+                });
+            } else {
+                self.hover = None;
             }
         }
 
         Ok(None)
     }
 
+    fn draw_shape(&self, t: &Texture2D, shape: &Shape, color: Color) {
+        match shape {
+            Shape::Rectangle { min, max } => {
+                let a = self.image_to_screen(t, *min);
+                let b = self.image_to_screen(t, *max);
+                draw_rectangle_lines(a.x, a.y, b.x - a.x, b.y - a.y, 2.0, color);
+            }
+            Shape::Ellipse { center, radius } => {
+                let c = self.image_to_screen(t, *center);
+                let scale = self.view_rect(t).w / t.width();
+                draw_ellipse_lines(
+                    c.x,
+                    c.y,
+                    radius.0 * scale,
+                    radius.1 * scale,
+                    0.0,
+                    2.0,
+                    color,
+                );
+            }
+            Shape::Polygon(points) => {
+                for (i, a) in points.iter().enumerate() {
+                    let a = self.image_to_screen(t, *a);
+                    let b = self.image_to_screen(t, points[(i + 1) % points.len()]);
+                    draw_line(a.x, a.y, b.x, b.y, 2.0, color);
+                }
+            }
+        }
+    }
+
     pub async fn draw(&mut self) -> Result<Option<AppState>> {
         self.images_height = 0.0;
         let images_len = self.images.len();
         let mut y = self.scroll;
         for (i, image) in self.images.iter_mut().enumerate() {
-            if !(*image.is_loading.lock().unwrap()) && image.data.lock().unwrap().is_none() {
-                image.load().context(format!(
-                    "Failed to load image data for {}",
-                    image.path.display()
-                ))?;
+            if !(*image.is_loading.lock().unwrap())
+                && !(*image.failed.lock().unwrap())
+                && image.data.lock().unwrap().is_none()
+            {
+                image
+                    .load(Arc::clone(&self.recognizer), self.folder_bar)
+                    .context(format!(
+                        "Failed to load image data for {}",
+                        image.path.display()
+                    ))?;
                 break;
             }
 
             if let Some(t) = &image.texture {
                 draw_texture_ex(
-                    &t,
+                    t,
                     0.0,
                     y,
                     WHITE,
@@ -272,39 +663,147 @@ impl BrowseData {
         let max_width = self.max_width;
         if let Some(image) = self.selected_image
             && self.loaded
+            && let Some(t) = &self.images[image].texture
         {
-            if let Some(t) = &self.images[image].texture {
-                let (width, height) = scale_texture(t.clone(), max_width);
-                draw_texture_ex(
-                    &t,
-                    max_width + 10.0,
-                    0.0,
-                    WHITE,
-                    DrawTextureParams {
-                        dest_size: Some(Vec2::new(width, height)),
-                        ..Default::default()
-                    },
-                );
+            let (width, height) = scale_texture(t.clone(), max_width);
+            draw_texture_ex(
+                t,
+                max_width + 10.0,
+                0.0,
+                WHITE,
+                DrawTextureParams {
+                    dest_size: Some(Vec2::new(width, height)),
+                    ..Default::default()
+                },
+            );
+
+            if self.tool == Tool::Calibrate {
+                if let Some(d) = self.images[image].data.lock().unwrap().as_ref() {
+                    let bar = d.effective_bar(self.folder_bar);
+                    let max = self.pixel_to_screen(t, (bar.max.0 as f32, bar.max.1 as f32));
+                    let min = self.pixel_to_screen(t, (bar.min.0 as f32, bar.min.1 as f32));
+                    let thickness = (bar.width as f32 * width / t.width()).max(2.0);
+                    draw_line(min.x, min.y, max.x, max.y, thickness, YELLOW);
+                    draw_circle(max.x, max.y, 4.0, RED);
+                    draw_circle(min.x, min.y, 4.0, BLUE);
+                }
+
+                if let Some((start, end)) = self.drag {
+                    let a = self.pixel_to_screen(t, (start.0 as f32, start.1 as f32));
+                    let b = self.pixel_to_screen(t, (end.0 as f32, end.1 as f32));
+                    if is_key_down(KeyCode::LeftShift) || is_key_down(KeyCode::RightShift) {
+                        let (x, y) = (a.x.min(b.x), a.y.min(b.y));
+                        draw_rectangle_lines(x, y, (a.x - b.x).abs(), (a.y - b.y).abs(), 2.0, RED);
+                    } else {
+                        draw_line(a.x, a.y, b.x, b.y, 2.0, RED);
+                    }
+                }
+            }
+
+            if let Some(d) = self.images[image].data.lock().unwrap().as_ref()
+                && self.images[image]
+                    .view
+                    .lock()
+                    .unwrap()
+                    .layer_enabled(Layer::Annotations)
+            {
+                for roi in &d.rois {
+                    self.draw_shape(t, &roi.shape, GREEN);
+                    let (min, _) = roi.shape.bounds();
+                    let p = self.image_to_screen(t, min);
+                    draw_text(&roi.name, p.x + 2.0, p.y - 4.0, 16.0, GREEN);
+                }
+
+                if self.hotspots.extremes {
+                    if let Some((p, temp)) = d.temperatures.hottest() {
+                        let p = self.pixel_to_screen(t, (p.0 as f32, p.1 as f32));
+                        draw_marker(p, &format!("Max {temp:.2}°C"), RED);
+                    }
+                    if let Some((p, temp)) = d.temperatures.coldest() {
+                        let p = self.pixel_to_screen(t, (p.0 as f32, p.1 as f32));
+                        draw_marker(p, &format!("Min {temp:.2}°C"), BLUE);
+                    }
+                }
+                for (i, (p, _)) in self.hotspots.found.iter().enumerate() {
+                    let p = self.pixel_to_screen(t, (p.0 as f32, p.1 as f32));
+                    draw_marker(p, &format!("H{}", i + 1), ORANGE);
+                }
+
+                for spot in &d.spots {
+                    let p =
+                        self.pixel_to_screen(t, (spot.position.0 as f32, spot.position.1 as f32));
+                    draw_circle_lines(p.x, p.y, 5.0, 2.0, WHITE);
+                    draw_line(p.x - 9.0, p.y, p.x - 3.0, p.y, 2.0, WHITE);
+                    draw_line(p.x + 3.0, p.y, p.x + 9.0, p.y, 2.0, WHITE);
+                    draw_line(p.x, p.y - 9.0, p.x, p.y - 3.0, 2.0, WHITE);
+                    draw_line(p.x, p.y + 3.0, p.x, p.y + 9.0, 2.0, WHITE);
+
+                    let label = match spot.temperature(&d.temperatures) {
+                        Some(temp) => format!("{} {temp:.2}°C", spot.name),
+                        None => spot.name.clone(),
+                    };
+                    draw_text(&label, p.x + 11.0, p.y - 3.0, 16.0, BLACK);
+                    draw_text(&label, p.x + 10.0, p.y - 4.0, 16.0, WHITE);
+                }
+            }
+
+            let line = match self.drag {
+                Some(line) if self.tool == Tool::Profile => Some(line),
+                _ => self.profile,
+            };
+            if let Some((start, end)) = line {
+                let a = self.pixel_to_screen(t, (start.0 as f32, start.1 as f32));
+                let b = self.pixel_to_screen(t, (end.0 as f32, end.1 as f32));
+                draw_line(a.x, a.y, b.x, b.y, 2.0, SKYBLUE);
+                draw_circle(a.x, a.y, 4.0, SKYBLUE);
+                draw_circle_lines(b.x, b.y, 4.0, 2.0, SKYBLUE);
+            }
+
+            if let Tool::Roi(kind) = self.tool {
+                if let Some((start, end)) = self.drag {
+                    let shape = if kind == ShapeKind::Ellipse {
+                        Shape::ellipse(start, end)
+                    } else {
+                        Shape::rectangle(start, end)
+                    };
+                    self.draw_shape(t, &shape, RED);
+                }
+                let mut points: Vec<Vec2> = self
+                    .polygon
+                    .iter()
+                    .map(|p| self.pixel_to_screen(t, (p.0 as f32, p.1 as f32)))
+                    .collect();
+                if !points.is_empty() {
+                    points.push(mouse_position().into());
+                }
+                for w in points.windows(2) {
+                    draw_line(w[0].x, w[0].y, w[1].x, w[1].y, 2.0, RED);
+                }
+                if let Some(first) = points.first() {
+                    draw_circle(first.x, first.y, 4.0, RED);
+                }
             }
         }
 
         egui_macroquad::ui(|egui_ctx| {
+            self.typing = egui_ctx.wants_keyboard_input();
             egui::SidePanel::right("properties")
                 .exact_width(175.0)
                 .show(egui_ctx, |ui| {
-                    let mut new_texture: Option<Texture2D> = None;
+                    let mut folder_action: Option<FolderAction> = None;
+                    let mut history_action: Option<HistoryAction> = None;
                     if let Some(image) = self.selected_image {
                         if let Some(d) = self.images[image].data.lock().unwrap().as_mut()
                             && let Some(t) = &self.images[image].texture
                         {
-                            ui.heading(format!(
-                                "{}",
+                            let mut view = self.images[image].view.lock().unwrap();
+                            ui.heading(
                                 self.images[image]
                                     .path
                                     .file_name()
                                     .unwrap_or_default()
-                                    .to_string_lossy()
-                            ));
+                                    .to_string_lossy(),
+                            );
                             if !self.loaded {
                                 ui.label(
                                     RichText::new("Loading images...")
@@ -323,136 +822,521 @@ impl BrowseData {
                                 ui.end_row();
 
                                 ui.label("Size");
-                                ui.label(format!("{} bytes", d.image.as_raw().len()));
+                                ui.label(format!("{} bytes", d.raw_image.as_raw().len()));
                                 ui.end_row();
                             });
 
+                            let history = &self.images[image].history;
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .add_enabled(!history.done.is_empty(), egui::Button::new("Undo"))
+                                    .on_hover_text("Ctrl+Z")
+                                    .clicked()
+                                {
+                                    history_action = Some(HistoryAction::Undo);
+                                }
+                                if ui
+                                    .add_enabled(!history.undone.is_empty(), egui::Button::new("Redo"))
+                                    .on_hover_text("Ctrl+Shift+Z")
+                                    .clicked()
+                                {
+                                    history_action = Some(HistoryAction::Redo);
+                                }
+                            });
+                            egui::CollapsingHeader::new("Notes").show(ui, |ui| {
+                                if ui
+                                    .add(egui::TextEdit::multiline(&mut view.notes).desired_rows(3))
+                                    .changed()
+                                {
+                                    self.project_dirty = true;
+                                }
+                            });
+                            egui::CollapsingHeader::new("History").show(ui, |ui| {
+                                if history.done.is_empty() && history.undone.is_empty() {
+                                    ui.label("No edits yet");
+                                }
+                                for change in &history.done {
+                                    ui.label(&change.name);
+                                }
+                                for change in history.undone.iter().rev() {
+                                    ui.label(
+                                        RichText::new(&change.name)
+                                            .color(Color32::from_rgb(110, 110, 110)),
+                                    )
+                                    .on_hover_text("Undone");
+                                }
+                            });
+
                             ui.separator();
 
                             ui.heading("Colors");
-                            Grid::new("controls").num_columns(2).show(ui, |ui| {
-                                ui.label("Max");
-                                ui.add(egui::DragValue::new(&mut d.max).speed(d.step));
-                                ui.end_row();
+                            let extract = if d.radiometric.is_some() {
+                                camera_ui(ui, d)
+                            } else {
+                                let folder_bar = self.folder_bar;
+                                let extract = bar_ui(
+                                    ui,
+                                    d,
+                                    &self.palettes,
+                                    &mut self.tool,
+                                    &mut self.folder_bar,
+                                    &mut self.recognizer,
+                                );
+                                if self.folder_bar != folder_bar {
+                                    self.project_dirty = true;
+                                }
+                                extract
+                            };
+                            if extract {
+                                self.images[image].extract(self.folder_bar);
+                            }
 
-                                ui.label("Min");
-                                ui.add(egui::DragValue::new(&mut d.min).speed(d.step));
-                                ui.end_row();
+                            ui.checkbox(&mut view.override_calibration, "Keep own calibration")
+                                .on_hover_text("Folder-wide actions skip this image");
+                            // Sensor data has no color map to hand on
+                            if d.radiometric.is_none()
+                                && ui
+                                    .button("Apply calibration to all")
+                                    .on_hover_text("Use this palette and range for every image")
+                                    .clicked()
+                            {
+                                folder_action = Some(FolderAction::ApplyCalibration(Box::new(d.clone())));
+                            }
+                            if ui.button("Extract color map for all").clicked() {
+                                folder_action = Some(FolderAction::ExtractAll);
+                            }
 
-                                ui.label("Step");
-                                ui.add(egui::DragValue::new(&mut d.step).speed(0.1));
-                                ui.end_row();
-                            });
+                            ui.separator();
 
-                            if ui.button("Extract color map").clicked() {
-                                d.color_temp =
-                                    extract_color_to_temp_map(&d.image, d.min, d.max, d.step);
-                            }
+                            ui.add_enabled_ui(!d.temperatures.is_empty(), |ui| {
+                                ui.heading("Recolor");
+
+                                Grid::new("recolor").num_columns(2).show(ui, |ui| {
+                                    ui.label("Palette");
+                                    egui::ComboBox::from_id_salt("recolor_palette")
+                                        .selected_text(d.recolor.palette.name.clone())
+                                        .show_ui(ui, |ui| {
+                                            for palette in &self.palettes {
+                                                ui.selectable_value(
+                                                    &mut d.recolor.palette,
+                                                    palette.clone(),
+                                                    &palette.name,
+                                                );
+                                            }
+                                        });
+                                    ui.end_row();
+
+                                    ui.label("Max");
+                                    ui.add(egui::DragValue::new(&mut d.recolor.max).speed(d.step));
+                                    ui.end_row();
+
+                                    ui.label("Min");
+                                    ui.add(egui::DragValue::new(&mut d.recolor.min).speed(d.step));
+                                    ui.end_row();
+                                });
+
+                                let mut show = view.layer_enabled(Layer::Palette);
+                                if ui.checkbox(&mut show, "Show recolored").changed() {
+                                    view.set_layer(Layer::Palette, show);
+                                }
+                            });
 
                             ui.separator();
 
-                            ui.add_enabled_ui(d.color_temp.len() > 0, |ui| {
+                            ui.add_enabled_ui(!d.temperatures.is_empty(), |ui| {
                                 ui.heading("Filter");
 
+                                let roi = self.histogram_roi.and_then(|i| d.rois.get(i));
+                                egui::ComboBox::from_id_salt("histogram_roi")
+                                    .selected_text(roi.map_or("Image", |r| r.name.as_str()))
+                                    .show_ui(ui, |ui| {
+                                        ui.selectable_value(&mut self.histogram_roi, None, "Image");
+                                        for (i, roi) in d.rois.iter().enumerate() {
+                                            ui.selectable_value(
+                                                &mut self.histogram_roi,
+                                                Some(i),
+                                                &roi.name,
+                                            );
+                                        }
+                                    });
+
+                                // Bins are whole steps, coarse enough to fit the panel. A
+                                // step from a project file may still be out of range.
+                                if d.step > 0.0 {
+                                    let steps = ((d.max - d.min) / d.step / 100.0).ceil().max(1.0);
+                                    let values: Vec<f32> = match roi {
+                                        Some(roi) => roi.temperatures(&d.temperatures).collect(),
+                                        None => d.temperatures.temperatures().collect(),
+                                    };
+                                    let histogram = Histogram::new(values, d.min, d.max, d.step * steps);
+                                    let range = (
+                                        d.filter.min.unwrap_or(d.min),
+                                        d.filter.max.unwrap_or(d.max),
+                                    );
+                                    if let Some((lo, hi)) = histogram_plot(ui, &histogram, range) {
+                                        d.filter = Filter {
+                                            min: Some((lo / d.step).round() * d.step),
+                                            max: Some((hi / d.step).round() * d.step),
+                                        };
+                                        view.set_layer(Layer::Filter, true);
+                                    }
+                                }
+
                                 Grid::new("filter").num_columns(4).min_col_width(10.0).show(
                                     ui,
                                     |ui| {
                                         ui.label("Max");
-                                        if ui.checkbox(&mut d.filter_max_enabled, "").clicked() {
-                                            d.filter_max = d.max;
-                                        }
-                                        if !d.filter_max_enabled {
-                                            d.filter_max = d.max;
-                                        }
-                                        ui.add_enabled_ui(d.filter_max_enabled, |ui| {
-                                            ui.add(
-                                                egui::DragValue::new(&mut d.filter_max)
-                                                    .speed(d.step),
-                                            );
-                                        });
-
+                                        filter_bound(ui, &mut d.filter.max, d.max, d.step);
                                         ui.end_row();
                                         ui.label("Min");
-                                        if ui.checkbox(&mut d.filter_min_enabled, "").clicked() {
-                                            d.filter_min = d.min;
-                                        }
-                                        if !d.filter_min_enabled {
-                                            d.filter_min = d.min;
-                                        }
-                                        ui.add_enabled_ui(d.filter_min_enabled, |ui| {
-                                            ui.add(
-                                                egui::DragValue::new(&mut d.filter_min)
-                                                    .speed(d.step),
-                                            );
-                                        });
+                                        filter_bound(ui, &mut d.filter.min, d.min, d.step);
                                         ui.end_row();
                                     },
                                 );
 
-                                if ui.button("Apply filter").clicked() {
-                                    let mut f = d.raw_image.clone();
-                                    for row in f.rows_mut() {
-                                        for pixel in row {
-                                            let temp = d.color_temp.get_closest_by(|color| {
-                                                let d =
-                                                    |a: u8, b: u8| (a as f32 - b as f32).powi(2);
-                                                d(color[0], pixel[0])
-                                                    + d(color[1], pixel[1])
-                                                    + d(color[2], pixel[2])
-                                            });
-                                            if let Some(temp) = temp {
-                                                if temp < d.filter_min || temp > d.filter_max {
-                                                    let r = pixel[0] as u32;
-                                                    let g = pixel[1] as u32;
-                                                    let b = pixel[2] as u32;
-
-                                                    let gray = (0.299 * r as f32
-                                                        + 0.587 * g as f32
-                                                        + 0.114 * b as f32)
-                                                        as u8;
-
-                                                    pixel[0] = gray / 2 + 128;
-                                                    pixel[1] = gray / 2 + 128;
-                                                    pixel[2] = gray / 2 + 128;
-                                                }
+                                let mut show = view.layer_enabled(Layer::Filter);
+                                if ui.checkbox(&mut show, "Show filter").changed() {
+                                    view.set_layer(Layer::Filter, show);
+                                }
+                            });
+
+                            ui.separator();
+
+                            ui.add_enabled_ui(!d.temperatures.is_empty(), |ui| {
+                                ui.heading("Isotherms");
+
+                                let mut remove = None;
+                                for (i, band) in d.isotherms.iter_mut().enumerate() {
+                                    ui.push_id(("isotherm", i), |ui| {
+                                        ui.horizontal(|ui| {
+                                            ui.checkbox(&mut band.enabled, "");
+                                            ui.color_edit_button_srgba_unmultiplied(&mut band.color);
+                                            egui::ComboBox::from_id_salt("fill")
+                                                .width(60.0)
+                                                .selected_text(match band.fill {
+                                                    Fill::Solid => "Solid",
+                                                    Fill::Striped => "Striped",
+                                                })
+                                                .show_ui(ui, |ui| {
+                                                    ui.selectable_value(&mut band.fill, Fill::Solid, "Solid");
+                                                    ui.selectable_value(&mut band.fill, Fill::Striped, "Striped");
+                                                });
+                                            if ui.small_button("x").on_hover_text("Delete").clicked() {
+                                                remove = Some(i);
                                             }
+                                        });
+                                        ui.horizontal(|ui| {
+                                            ui.add(egui::DragValue::new(&mut band.min).speed(d.step).suffix("°C"));
+                                            ui.label("to");
+                                            ui.add(egui::DragValue::new(&mut band.max).speed(d.step).suffix("°C"));
+                                        });
+                                    });
+                                }
+                                if let Some(i) = remove {
+                                    d.isotherms.remove(i);
+                                }
+
+                                if ui.button("Add band").clicked() {
+                                    let quarter = (d.max - d.min) / 4.0;
+                                    d.isotherms.push(Isotherm {
+                                        enabled: true,
+                                        min: d.max - quarter,
+                                        max: d.max,
+                                        color: isotherm::COLORS[d.isotherms.len() % isotherm::COLORS.len()],
+                                        fill: Fill::Solid,
+                                    });
+                                }
+                            });
+
+                            ui.separator();
+
+                            ui.heading("Layers")
+                                .on_hover_text("Applied from top to bottom over the original image");
+                            let mut swap = None;
+                            let count = view.layers.len();
+                            for (i, op) in view.layers.iter_mut().enumerate() {
+                                ui.horizontal(|ui| {
+                                    ui.checkbox(&mut op.enabled, op.layer.name());
+                                    ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                                        if ui.add_enabled(i + 1 < count, egui::Button::new("v").small()).clicked() {
+                                            swap = Some(i);
                                         }
+                                        if ui.add_enabled(i > 0, egui::Button::new("^").small()).clicked() {
+                                            swap = Some(i - 1);
+                                        }
+                                    });
+                                });
+                            }
+                            if let Some(i) = swap {
+                                view.layers.swap(i, i + 1);
+                            }
+
+                            ui.separator();
+
+                            ui.heading("Regions");
+                            ui.horizontal_wrapped(|ui| {
+                                for kind in [ShapeKind::Rectangle, ShapeKind::Ellipse, ShapeKind::Polygon] {
+                                    let hint = if kind == ShapeKind::Polygon {
+                                        "Click the corners, then Enter or the first corner to close"
+                                    } else {
+                                        "Drag over the image"
+                                    };
+                                    if ui
+                                        .selectable_label(self.tool == Tool::Roi(kind), kind.name())
+                                        .on_hover_text(hint)
+                                        .clicked()
+                                    {
+                                        self.tool = if self.tool == Tool::Roi(kind) {
+                                            Tool::None
+                                        } else {
+                                            Tool::Roi(kind)
+                                        };
+                                        self.drag = None;
+                                        self.polygon.clear();
                                     }
-                                    d.image = f.clone();
-                                    new_texture = Some(Texture2D::from_rgba8(
-                                        f.width() as u16,
-                                        f.height() as u16,
-                                        f.as_raw(),
+                                }
+                            });
+
+                            let mut remove = None;
+                            for (i, roi) in d.rois.iter_mut().enumerate() {
+                                ui.horizontal(|ui| {
+                                    ui.add(
+                                        egui::TextEdit::singleline(&mut roi.name)
+                                            .desired_width(120.0),
+                                    );
+                                    if ui.small_button("x").on_hover_text("Delete").clicked() {
+                                        remove = Some(i);
+                                    }
+                                });
+                                let stats = roi.stats(&d.temperatures);
+                                Grid::new(("roi", i)).num_columns(2).show(ui, |ui| {
+                                    ui.label("Area");
+                                    ui.label(format!("{} px", roi.area(&d.temperatures)));
+                                    ui.end_row();
+
+                                    let rows = [
+                                        ("Min", stats.map(|s| s.min)),
+                                        ("Max", stats.map(|s| s.max)),
+                                        ("Mean", stats.map(|s| s.mean)),
+                                        ("Std dev", stats.map(|s| s.std_dev)),
+                                    ];
+                                    for (label, value) in rows {
+                                        ui.label(label);
+                                        ui.label(value.map_or("-".to_string(), |v| format!("{v:.2}°C")));
+                                        ui.end_row();
+                                    }
+                                });
+                            }
+                            if let Some(i) = remove {
+                                d.rois.remove(i);
+                            }
+
+                            ui.separator();
+
+                            ui.heading("Spots")
+                                .on_hover_text("Right-click the image to add or remove a spot, drag one to move it");
+                            let mut remove = None;
+                            Grid::new("spots").num_columns(3).show(ui, |ui| {
+                                for (i, spot) in d.spots.iter_mut().enumerate() {
+                                    ui.add(
+                                        egui::TextEdit::singleline(&mut spot.name)
+                                            .desired_width(50.0),
+                                    );
+                                    ui.label(
+                                        spot.temperature(&d.temperatures)
+                                            .map_or("-".to_string(), |t| format!("{t:.2}°C")),
+                                    )
+                                    .on_hover_text(format!(
+                                        "x {}, y {}",
+                                        spot.position.0, spot.position.1
                                     ));
+                                    if ui.small_button("x").on_hover_text("Delete").clicked() {
+                                        remove = Some(i);
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                            if let Some(i) = remove {
+                                d.spots.remove(i);
+                            }
+
+                            ui.separator();
+
+                            ui.heading("Profile");
+                            ui.horizontal(|ui| {
+                                if ui
+                                    .selectable_label(self.tool == Tool::Profile, "Draw line")
+                                    .on_hover_text("Drag across the image to plot temperatures along the line")
+                                    .clicked()
+                                {
+                                    self.tool = if self.tool == Tool::Profile {
+                                        Tool::None
+                                    } else {
+                                        Tool::Profile
+                                    };
+                                    self.drag = None;
+                                }
+                                if ui
+                                    .add_enabled(self.profile.is_some(), egui::Button::new("Clear"))
+                                    .clicked()
+                                {
+                                    self.profile = None;
+                                }
+                            });
+
+                            ui.separator();
+
+                            ui.heading("Hotspots");
+                            ui.checkbox(&mut self.hotspots.extremes, "Mark hottest and coldest");
+                            if self.hotspots.extremes {
+                                Grid::new("extremes").num_columns(2).show(ui, |ui| {
+                                    for (label, extreme) in [
+                                        ("Max", d.temperatures.hottest()),
+                                        ("Min", d.temperatures.coldest()),
+                                    ] {
+                                        ui.label(label);
+                                        match extreme {
+                                            Some((p, temp)) => {
+                                                ui.label(format!("{temp:.2}°C"))
+                                                    .on_hover_text(format!("x {}, y {}", p.0, p.1));
+                                            }
+                                            None => {
+                                                ui.label("-");
+                                            }
+                                        }
+                                        ui.end_row();
+                                    }
+                                });
+                            }
+
+                            Grid::new("hotspots").num_columns(3).min_col_width(10.0).show(ui, |ui| {
+                                ui.label("Count");
+                                ui.label("");
+                                ui.add(egui::DragValue::new(&mut self.hotspots.count).range(1..=50));
+                                ui.end_row();
+
+                                ui.label("Above");
+                                let mut above = self.hotspots.threshold.is_some();
+                                if ui.checkbox(&mut above, "").clicked() {
+                                    self.hotspots.threshold =
+                                        above.then_some(d.max - (d.max - d.min) / 4.0);
+                                }
+                                ui.add_enabled_ui(above, |ui| {
+                                    let mut threshold = self.hotspots.threshold.unwrap_or(d.max);
+                                    ui.add(egui::DragValue::new(&mut threshold).speed(d.step));
+                                    if above {
+                                        self.hotspots.threshold = Some(threshold);
+                                    }
+                                });
+                                ui.end_row();
+
+                                ui.label("Spacing");
+                                ui.label("");
+                                ui.add(
+                                    egui::DragValue::new(&mut self.hotspots.spacing)
+                                        .range(1..=200)
+                                        .suffix(" px"),
+                                )
+                                .on_hover_text("Closest distance between two hotspots");
+                                ui.end_row();
+                            });
+
+                            ui.horizontal(|ui| {
+                                if ui.button("Find hotspots").clicked() {
+                                    self.hotspots.found = d.temperatures.local_maxima(
+                                        self.hotspots.count,
+                                        self.hotspots.threshold.unwrap_or(f32::NEG_INFINITY),
+                                        self.hotspots.spacing,
+                                    );
+                                }
+                                if ui
+                                    .add_enabled(
+                                        !self.hotspots.found.is_empty(),
+                                        egui::Button::new("Clear"),
+                                    )
+                                    .clicked()
+                                {
+                                    self.hotspots.found.clear();
+                                }
+                            });
+                            Grid::new("found").num_columns(2).show(ui, |ui| {
+                                for (i, (p, temp)) in self.hotspots.found.iter().enumerate() {
+                                    ui.label(format!("H{}", i + 1));
+                                    ui.label(format!("{temp:.2}°C"))
+                                        .on_hover_text(format!("x {}, y {}", p.0, p.1));
+                                    ui.end_row();
                                 }
                             });
 
                             ui.separator();
 
-                            if let Some(hover) = self.hover {
+                            if let Some((hover, distance)) = self.hover {
                                 ui.label(RichText::new(format!("Hover: {:.2}°C ", hover)));
+                                ui.label(
+                                    RichText::new(format!("Distance: {:.1}", distance))
+                                        .color(Color32::from_rgb(150, 150, 150)),
+                                )
+                                .on_hover_text("RGB distance between the pixel and the palette");
                             }
 
                             if ui.button("Save current").clicked() {
                                 self.save_dialog.save_file();
                             }
 
-                            if let Some(path) = self.save_dialog.take_picked() {
-                                if let Err(e) = d.image.clone().save(path) {
-                                    eprintln!("Failed to save image: {}", e);
-                                }
+                            if let Some(path) = self.save_dialog.take_picked()
+                                && let Err(e) = export::save(&path, d, &view.layers)
+                            {
+                                eprintln!("Failed to save {}: {e:#}", path.display());
                             }
                         }
                     } else {
                         ui.label(RichText::new("No image selected"));
                     }
 
-                    if let Some(new_texture) = new_texture {
-                        self.images[self.selected_image.unwrap()].texture = Some(new_texture);
+                    let folder_bar = self.folder_bar;
+                    if let Some(image) = self.selected_image {
+                        match history_action {
+                            Some(HistoryAction::Undo) => self.images[image].undo(folder_bar),
+                            Some(HistoryAction::Redo) => self.images[image].redo(folder_bar),
+                            None => {}
+                        }
+                        if history_action.is_some() {
+                            self.project_dirty = true;
+                        }
+                    }
+                    // Other images are not tracked, their results are saved once
+                    // they are in
+                    if folder_action.is_some() {
+                        self.project_dirty = true;
+                    }
+                    match folder_action {
+                        Some(FolderAction::ApplyCalibration(from)) => {
+                            // Taking over the range is quick, only the temperatures
+                            // are worked out in the background
+                            for image in &self.images {
+                                if *image.is_loading.lock().unwrap() {
+                                    continue;
+                                }
+                                if let Some(d) = image.data.lock().unwrap().as_mut()
+                                    && !image.view.lock().unwrap().override_calibration
+                                {
+                                    d.take_calibration(&from);
+                                }
+                            }
+                            process_all(calibrated(&self.images), move |d| {
+                                Some(d.extraction(folder_bar, false))
+                            });
+                        }
+                        Some(FolderAction::ExtractAll) => {
+                            process_all(calibrated(&self.images), move |d| {
+                                Some(d.extraction(folder_bar, true))
+                            });
+                        }
+                        None => {}
                     }
                 });
 
-            if let Some(hover) = &self.hover {
+            if let Some((hover, _)) = &self.hover {
                 let mouse_pos = mouse_position();
                 egui::Window::new("Temp")
                     .collapsible(false)
@@ -470,6 +1354,21 @@ impl BrowseData {
                     });
             }
 
+            if let Some((start, end)) = self.profile
+                && let Some(image) = self.selected_image
+                && let Some(d) = self.images[image].data.lock().unwrap().as_ref()
+            {
+                let samples = d.temperatures.profile(start, end);
+                let mut open = true;
+                egui::Window::new("Profile")
+                    .open(&mut open)
+                    .default_size([360.0, 200.0])
+                    .show(egui_ctx, |ui| profile_plot(ui, &samples));
+                if !open {
+                    self.profile = None;
+                }
+            }
+
             self.save_dialog.update(egui_ctx);
         });
         egui_macroquad::draw();
@@ -508,6 +1407,15 @@ impl App {
         Ok(())
     }
 
+    // Saves edits still waiting for a pause, before the window closes
+    pub fn quit(&mut self) {
+        if let AppState::Browse(d) = &mut self.state
+            && d.last_edit.take().is_some()
+        {
+            d.save_project();
+        }
+    }
+
     pub async fn draw(&mut self) -> Result<()> {
         clear_background(DARKGRAY);
         let new_state = match &mut self.state {
@@ -521,37 +1429,558 @@ impl App {
     }
 }
 
-const BAR_X: f32 = 290.0;
-const BAR_MAX: f32 = 60.0;
-const BAR_MIN: f32 = 191.0;
+// Small triangle pointing at `p` with a label next to it
+fn draw_marker(p: Vec2, label: &str, color: Color) {
+    draw_triangle_lines(
+        p,
+        Vec2::new(p.x - 6.0, p.y - 10.0),
+        Vec2::new(p.x + 6.0, p.y - 10.0),
+        2.0,
+        color,
+    );
+    draw_text(label, p.x + 9.0, p.y - 9.0, 16.0, BLACK);
+    draw_text(label, p.x + 8.0, p.y - 10.0, 16.0, color);
+}
+
+// Checkbox and value of one end of the filter, showing the end of the range
+// while it is off
+fn filter_bound(ui: &mut egui::Ui, bound: &mut Option<f32>, end: f32, speed: f32) {
+    let mut enabled = bound.is_some();
+    if ui.checkbox(&mut enabled, "").changed() {
+        *bound = enabled.then_some(end);
+    }
+    let mut value = bound.unwrap_or(end);
+    ui.add_enabled_ui(enabled, |ui| {
+        if ui
+            .add(egui::DragValue::new(&mut value).speed(speed))
+            .changed()
+        {
+            *bound = Some(value);
+        }
+    });
+}
+
+// Images that folder-wide actions apply to
+fn calibrated(images: &[Image]) -> impl Iterator<Item = &Image> {
+    images
+        .iter()
+        .filter(|image| !image.view.lock().unwrap().override_calibration)
+}
 
-fn extract_color_to_temp_map(
-    img: &image::RgbaImage,
-    min_temp: f32,
-    max_temp: f32,
-    step: f32,
-) -> Map<[u8; 3], f32> {
-    let x = BAR_X;
-    let y = BAR_MAX;
-    let width = 1.0;
-    let height = BAR_MIN - BAR_MAX;
+// Bars per bin with the filter range shaded. Dragging across it gives a new
+// range.
+fn histogram_plot(
+    ui: &mut egui::Ui,
+    histogram: &Histogram,
+    range: (f32, f32),
+) -> Option<(f32, f32)> {
+    let size = egui::vec2(ui.available_width(), 80.0);
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::drag());
+    let rect = response.rect;
+    painter.rect_filled(rect, 0.0, Color32::from_gray(20));
 
-    let steps = ((max_temp - min_temp) / step).round() as u32;
-    let mut map = Map::new();
+    let span = (histogram.max() - histogram.min).max(f32::EPSILON);
+    let x_of = |t: f32| rect.left() + (t - histogram.min) / span * rect.width();
+    let temp_at =
+        |x: f32| histogram.min + ((x - rect.left()) / rect.width()).clamp(0.0, 1.0) * span;
 
-    for i in 0..=steps {
-        let offset = ((i as f32 / steps as f32) * height as f32).round() as u32;
-        let py = y + height - offset as f32;
-        let px = x + width as f32 / 2.0;
+    let (a, b) = (
+        x_of(range.0).max(rect.left()),
+        x_of(range.1).min(rect.right()),
+    );
+    if b > a {
+        painter.rect_filled(
+            egui::Rect::from_x_y_ranges(a..=b, rect.y_range()),
+            0.0,
+            Color32::from_rgba_unmultiplied(255, 160, 40, 30),
+        );
+    }
 
-        let pixel = img.get_pixel(px as u32, py as u32);
-        let rgb = [pixel[0], pixel[1], pixel[2]];
-        let temp = min_temp + i as f32 * step;
+    let max_count = histogram.counts.iter().copied().max().unwrap_or(0).max(1) as f32;
+    let bar_width = rect.width() / histogram.counts.len() as f32;
+    for (i, &count) in histogram.counts.iter().enumerate() {
+        if count == 0 {
+            continue;
+        }
+        let center = histogram.min + (i as f32 + 0.5) * histogram.bin_width;
+        let color = if center >= range.0 && center <= range.1 {
+            Color32::from_rgb(255, 160, 40)
+        } else {
+            Color32::from_gray(120)
+        };
+        let x0 = rect.left() + i as f32 * bar_width;
+        let h = count as f32 / max_count * rect.height();
+        painter.rect_filled(
+            egui::Rect::from_min_max(
+                egui::pos2(x0, rect.bottom() - h),
+                egui::pos2(x0 + bar_width.max(1.0), rect.bottom()),
+            ),
+            0.0,
+            color,
+        );
+    }
 
-        map.push(rgb, temp);
+    let response = response.on_hover_text("Drag to set the filter range");
+    let id = response.id;
+    if response.drag_started()
+        && let Some(pos) = response.interact_pointer_pos()
+    {
+        ui.data_mut(|data| data.insert_temp(id, temp_at(pos.x)));
     }
+    let start: Option<f32> = ui.data(|data| data.get_temp(id));
+    let pos = response
+        .interact_pointer_pos()
+        .or(ui.ctx().pointer_latest_pos());
+    if let (Some(start), Some(pos)) = (start, pos)
+        && (response.dragged() || response.drag_stopped())
+    {
+        let end = temp_at(pos.x);
+        if response.drag_stopped() {
+            ui.data_mut(|data| data.remove::<f32>(id));
+        }
+        return Some((start.min(end), start.max(end)));
+    }
+    None
+}
+
+// Temperature against distance, with gaps where pixels have no temperature
+fn profile_plot(ui: &mut egui::Ui, samples: &[(f32, Option<f32>)]) {
+    let stats = Stats::from_values(samples.iter().filter_map(|s| s.1));
+    if let Some(s) = stats {
+        ui.label(format!(
+            "Min {:.2}°C  Max {:.2}°C  Mean {:.2}°C",
+            s.min, s.max, s.mean
+        ));
+    }
+
+    let size = ui.available_size().max(egui::vec2(300.0, 150.0));
+    let (response, painter) = ui.allocate_painter(size, egui::Sense::hover());
+    let plot = egui::Rect::from_min_max(
+        response.rect.min + egui::vec2(40.0, 6.0),
+        response.rect.max - egui::vec2(8.0, 18.0),
+    );
+    let text_color = Color32::from_rgb(150, 150, 150);
+    let font = egui::FontId::proportional(11.0);
+    painter.rect_filled(plot, 0.0, Color32::from_gray(20));
+
+    let Some(s) = stats else {
+        painter.text(
+            plot.center(),
+            egui::Align2::CENTER_CENTER,
+            "No temperatures along the line",
+            font,
+            text_color,
+        );
+        return;
+    };
+
+    let length = samples.last().map_or(0.0, |s| s.0).max(1.0);
+    let (lo, hi) = (s.min, s.max.max(s.min + 0.1));
+    let to_screen = |distance: f32, temp: f32| {
+        egui::pos2(
+            plot.left() + distance / length * plot.width(),
+            plot.bottom() - (temp - lo) / (hi - lo) * plot.height(),
+        )
+    };
+
+    let stroke = egui::Stroke::new(1.5, Color32::from_rgb(255, 160, 40));
+    for run in samples.split(|s| s.1.is_none()) {
+        let points: Vec<_> = run
+            .iter()
+            .filter_map(|&(distance, temp)| temp.map(|t| to_screen(distance, t)))
+            .collect();
+        match points[..] {
+            [] => {}
+            [p] => {
+                painter.circle_filled(p, 1.5, stroke.color);
+            }
+            _ => {
+                painter.add(egui::Shape::line(points, stroke));
+            }
+        }
+    }
+
+    painter.text(
+        plot.left_top(),
+        egui::Align2::RIGHT_TOP,
+        format!("{hi:.1} "),
+        font.clone(),
+        text_color,
+    );
+    painter.text(
+        plot.left_bottom(),
+        egui::Align2::RIGHT_BOTTOM,
+        format!("{lo:.1} "),
+        font.clone(),
+        text_color,
+    );
+    painter.text(
+        plot.left_bottom(),
+        egui::Align2::LEFT_TOP,
+        "0",
+        font.clone(),
+        text_color,
+    );
+    painter.text(
+        plot.right_bottom(),
+        egui::Align2::RIGHT_TOP,
+        format!("{length:.0} px"),
+        font.clone(),
+        text_color,
+    );
 
-    map
+    if let Some(pos) = response.hover_pos()
+        && plot.contains(pos)
+    {
+        let distance = (pos.x - plot.left()) / plot.width() * length;
+        let nearest = samples.iter().min_by(|a, b| {
+            (a.0 - distance)
+                .abs()
+                .partial_cmp(&(b.0 - distance).abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        if let Some(&(distance, temp)) = nearest {
+            let x = to_screen(distance, lo).x;
+            painter.vline(x, plot.y_range(), egui::Stroke::new(1.0, text_color));
+            let label = match temp {
+                Some(t) => format!("{distance:.0} px: {t:.2}°C"),
+                None => format!("{distance:.0} px: -"),
+            };
+            painter.text(
+                egui::pos2(x + 4.0, plot.top()),
+                egui::Align2::LEFT_TOP,
+                label,
+                font,
+                Color32::WHITE,
+            );
+        }
+    }
+}
+
+// Sensor data and measurement parameters of a radiometric image, returning
+// whether the temperatures should be computed again
+fn camera_ui(ui: &mut egui::Ui, d: &mut ImageData) -> bool {
+    let Some(r) = &mut d.radiometric else {
+        return false;
+    };
+    ui.label(format!(
+        "{} sensor data, {}x{}",
+        r.camera.name(),
+        r.width,
+        r.height
+    ));
+    Grid::new("camera").num_columns(2).show(ui, |ui| {
+        match &mut r.camera {
+            Camera::Flir(c) => flir_rows(ui, c),
+            Camera::Dji(p) => dji_rows(ui, p),
+            Camera::Counts(scale) => counts_rows(ui, scale),
+        }
+
+        ui.label("Max");
+        ui.add(egui::DragValue::new(&mut d.max).speed(d.step));
+        ui.end_row();
+
+        ui.label("Min");
+        ui.add(egui::DragValue::new(&mut d.min).speed(d.step));
+        ui.end_row();
+    });
+
+    let mut compute = false;
+    ui.horizontal(|ui| {
+        compute = ui.button("Compute temperatures").clicked();
+        if ui
+            .button("Fit range")
+            .on_hover_text("Set Max, Min and the recolor range from the temperatures")
+            .clicked()
+        {
+            d.fit_range();
+        }
+    });
+    compute
+}
+
+fn flir_rows(ui: &mut egui::Ui, c: &mut CameraInfo) {
+    ui.label("Emissivity");
+    ui.add(
+        egui::DragValue::new(&mut c.emissivity)
+            .range(0.01..=1.0)
+            .speed(0.01),
+    );
+    ui.end_row();
+
+    ui.label("Reflected");
+    ui.add(temperature_value(&mut c.reflected_temperature));
+    ui.end_row();
+
+    ui.label("Atmosphere");
+    ui.add(temperature_value(&mut c.atmospheric_temperature));
+    ui.end_row();
+
+    ui.label("Humidity");
+    ui.add(
+        egui::DragValue::new(&mut c.relative_humidity)
+            .range(0.0..=1.0)
+            .speed(0.01),
+    );
+    ui.end_row();
+
+    ui.label("Distance");
+    ui.add(distance_value(&mut c.object_distance));
+    ui.end_row();
+
+    ui.label("IR window");
+    ui.add(temperature_value(&mut c.ir_window_temperature));
+    ui.end_row();
+
+    ui.label("Transmission");
+    ui.add(
+        egui::DragValue::new(&mut c.ir_window_transmission)
+            .range(0.01..=1.0)
+            .speed(0.01),
+    );
+    ui.end_row();
+}
+
+fn dji_rows(ui: &mut egui::Ui, p: &mut Parameters) {
+    ui.label("Emissivity");
+    ui.add(
+        egui::DragValue::new(&mut p.emissivity)
+            .range(0.01..=1.0)
+            .speed(0.01),
+    );
+    ui.end_row();
+
+    ui.label("Reflected");
+    ui.add(temperature_value(&mut p.reflected_temperature));
+    ui.end_row();
+
+    ui.label("Ambient");
+    ui.add(temperature_value(&mut p.ambient_temperature));
+    ui.end_row();
+
+    ui.label("Humidity");
+    ui.add(
+        egui::DragValue::new(&mut p.relative_humidity)
+            .range(0.0..=1.0)
+            .speed(0.01),
+    );
+    ui.end_row();
+
+    ui.label("Distance");
+    ui.add(distance_value(&mut p.object_distance));
+    ui.end_row();
+}
+
+fn counts_rows(ui: &mut egui::Ui, scale: &mut Scale) {
+    ui.label("Counts");
+    egui::ComboBox::from_id_salt("scale")
+        .selected_text(scale.name())
+        .show_ui(ui, |ui| {
+            ui.selectable_value(scale, Scale::Centikelvin, Scale::Centikelvin.name());
+            let linear = matches!(scale, Scale::Linear { .. });
+            // Starts out the same as centikelvin
+            if ui.selectable_label(linear, "Gain and offset").clicked() && !linear {
+                *scale = Scale::Linear {
+                    gain: 0.01,
+                    offset: -KELVIN,
+                };
+            }
+        });
+    ui.end_row();
+
+    if let Scale::Linear { gain, offset } = scale {
+        ui.label("Gain");
+        ui.add(egui::DragValue::new(gain).speed(0.0001));
+        ui.end_row();
+
+        ui.label("Offset");
+        ui.add(temperature_value(offset));
+        ui.end_row();
+    }
+}
+
+fn temperature_value(value: &mut f32) -> egui::DragValue<'_> {
+    egui::DragValue::new(value).speed(0.1).suffix("°C")
+}
+
+fn distance_value(value: &mut f32) -> egui::DragValue<'_> {
+    egui::DragValue::new(value)
+        .range(0.0..=f32::MAX)
+        .speed(0.1)
+        .suffix(" m")
+}
+
+// Scale bar or palette the colors of an image are read with, returning
+// whether its color map should be extracted again
+fn bar_ui(
+    ui: &mut egui::Ui,
+    d: &mut ImageData,
+    palettes: &[Palette],
+    tool: &mut Tool,
+    folder_bar: &mut Option<Bar>,
+    recognizer: &mut Arc<Recognizer>,
+) -> bool {
+    ui.horizontal(|ui| {
+        ui.label("Source");
+        let source = d
+            .palette
+            .as_ref()
+            .map_or("Scale bar".to_string(), |p| p.name.clone());
+        egui::ComboBox::from_id_salt("source")
+            .selected_text(source)
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut d.palette, None, "Scale bar");
+                for palette in palettes {
+                    ui.selectable_value(&mut d.palette, Some(palette.clone()), &palette.name);
+                }
+            });
+    });
+
+    let lookup = d.lookup;
+    Grid::new("controls").num_columns(3).show(ui, |ui| {
+        ui.label("Max");
+        ui.add(egui::DragValue::new(&mut d.max).speed(d.step));
+        reading_label(ui, d.max_reading);
+        ui.end_row();
+
+        ui.label("Min");
+        ui.add(egui::DragValue::new(&mut d.min).speed(d.step));
+        reading_label(ui, d.min_reading);
+        ui.end_row();
+
+        ui.label("Step");
+        ui.add(
+            egui::DragValue::new(&mut d.step)
+                .range(0.001..=f32::MAX)
+                .speed(0.1),
+        );
+        ui.end_row();
+
+        ui.label("Lookup");
+        egui::ComboBox::from_id_salt("lookup")
+            .selected_text(format!("{:?}", d.lookup))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut d.lookup, Lookup::Nearest, "Nearest");
+                ui.selectable_value(&mut d.lookup, Lookup::Interpolated, "Interpolated");
+            });
+        ui.end_row();
+    });
+    let mut extract = d.lookup != lookup && !d.color_temp.is_empty();
+
+    if d.palette.is_none() {
+        let mut bar = d.effective_bar(*folder_bar);
+        Grid::new("bar").num_columns(2).show(ui, |ui| {
+            ui.label("Bar");
+            ui.label(format!(
+                "{},{} → {},{}",
+                bar.max.0, bar.max.1, bar.min.0, bar.min.1
+            ));
+            ui.end_row();
+
+            ui.label("Width");
+            if ui
+                .add(egui::DragValue::new(&mut bar.width).range(1..=64))
+                .changed()
+            {
+                d.bar = Some(bar);
+            }
+            ui.end_row();
+
+            ui.label("Samples");
+            ui.label(format!("{}", d.color_temp.len()));
+            ui.end_row();
+        });
+
+        ui.horizontal(|ui| {
+            if ui
+                .selectable_label(*tool == Tool::Calibrate, "Calibrate")
+                .on_hover_text("Drag along the scale bar, hold Shift to drag a rectangle")
+                .clicked()
+            {
+                *tool = if *tool == Tool::Calibrate {
+                    Tool::None
+                } else {
+                    Tool::Calibrate
+                };
+            }
+            if ui.button("Swap").clicked() {
+                d.bar = Some(Bar::new(bar.min, bar.max, bar.width));
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button("Use for folder").clicked() {
+                *folder_bar = Some(bar);
+            }
+            if ui
+                .add_enabled(d.bar.is_some(), egui::Button::new("Reset"))
+                .clicked()
+            {
+                d.bar = None;
+            }
+            if ui.button("Detect").clicked() {
+                d.detected_bar = detect_bar(&d.raw_image);
+                d.bar = None;
+            }
+        });
+
+        ui.horizontal(|ui| {
+            if ui.button("Read labels").clicked() {
+                let bar = d.effective_bar(*folder_bar);
+                (d.max_reading, d.min_reading) = recognizer.read_bar_labels(&d.raw_image, &bar);
+                if let (Some(lo), Some(hi)) = (d.min_reading, d.max_reading)
+                    && lo.value < hi.value
+                {
+                    d.min = lo.value;
+                    d.max = hi.value;
+                }
+            }
+            if ui
+                .button("Train")
+                .on_hover_text("Save the labels as samples of the current Max and Min")
+                .clicked()
+            {
+                for (reading, value) in [(d.max_reading, d.max), (d.min_reading, d.min)] {
+                    if let Some(r) = reading
+                        && let Err(e) = save_sample(&d.raw_image, &r, value)
+                    {
+                        eprintln!("Failed to save OCR sample: {e}");
+                    }
+                }
+                *recognizer = Arc::new(Recognizer::load(Path::new(TEMPLATES_DIR)));
+            }
+        });
+    }
+
+    let label = if d.palette.is_some() {
+        "Apply palette"
+    } else {
+        "Extract color map"
+    };
+    if ui.button(label).clicked() {
+        extract = true;
+    }
+    extract
+}
+
+fn reading_label(ui: &mut egui::Ui, reading: Option<Reading>) {
+    match reading {
+        Some(r) => {
+            let color = if r.confidence >= 0.85 {
+                Color32::from_rgb(100, 200, 100)
+            } else if r.confidence >= 0.7 {
+                Color32::from_rgb(220, 180, 60)
+            } else {
+                Color32::from_rgb(220, 80, 80)
+            };
+            ui.label(RichText::new(format!("{:.0}%", r.confidence * 100.0)).color(color))
+                .on_hover_text(format!("Read {} from the overlay", r.value));
+        }
+        None => {
+            ui.label(RichText::new("—").color(Color32::from_rgb(150, 150, 150)))
+                .on_hover_text("No label read");
+        }
+    }
 }
 
 fn scale_texture(t: Texture2D, max_width: f32) -> (f32, f32) {
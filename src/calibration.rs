@@ -0,0 +1,422 @@
+use image::RgbaImage;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    field::TemperatureField,
+    map::{Lookup, Map},
+    ocr::{Reading, Recognizer, Rect},
+    palette::Palette,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Bar {
+    pub max: (u32, u32),
+    pub min: (u32, u32),
+    pub width: u32,
+}
+
+impl Default for Bar {
+    fn default() -> Self {
+        Bar {
+            max: (290, 60),
+            min: (290, 191),
+            width: 1,
+        }
+    }
+}
+
+impl Bar {
+    pub fn new(max: (u32, u32), min: (u32, u32), width: u32) -> Bar {
+        Bar {
+            max,
+            min,
+            width: width.max(1),
+        }
+    }
+
+    // A dragged rectangle becomes a bar along its longer side. Vertical bars
+    // are hot at the top, horizontal bars are hot on the right.
+    pub fn from_rect(a: (u32, u32), b: (u32, u32)) -> Bar {
+        let (x0, x1) = (a.0.min(b.0), a.0.max(b.0));
+        let (y0, y1) = (a.1.min(b.1), a.1.max(b.1));
+        let (w, h) = (x1 - x0 + 1, y1 - y0 + 1);
+        if h >= w {
+            let x = x0 + w / 2;
+            Bar::new((x, y0), (x, y1), w)
+        } else {
+            let y = y0 + h / 2;
+            Bar::new((x1, y), (x0, y), h)
+        }
+    }
+
+    pub fn is_vertical(&self) -> bool {
+        self.max.0.abs_diff(self.min.0) <= self.max.1.abs_diff(self.min.1)
+    }
+
+    pub fn length(&self) -> f32 {
+        let dx = self.max.0 as f32 - self.min.0 as f32;
+        let dy = self.max.1 as f32 - self.min.1 as f32;
+        (dx * dx + dy * dy).sqrt()
+    }
+
+    // Point on the bar, `t` going from 0.0 at the cold end to 1.0 at the hot end.
+    pub fn point_at(&self, t: f32) -> (f32, f32) {
+        (
+            self.min.0 as f32 + (self.max.0 as f32 - self.min.0 as f32) * t,
+            self.min.1 as f32 + (self.max.1 as f32 - self.min.1 as f32) * t,
+        )
+    }
+
+    // Average color across the bar width at `t`, skipping pixels outside the image.
+    pub fn sample(&self, img: &RgbaImage, t: f32) -> Option<[u8; 3]> {
+        let (cx, cy) = self.point_at(t);
+        let len = self.length().max(1.0);
+        let nx = -(self.max.1 as f32 - self.min.1 as f32) / len;
+        let ny = (self.max.0 as f32 - self.min.0 as f32) / len;
+
+        let mut sum = [0u32; 3];
+        let mut count = 0;
+        for i in 0..self.width {
+            let o = i as f32 - (self.width - 1) as f32 / 2.0;
+            let (x, y) = ((cx + nx * o).round(), (cy + ny * o).round());
+            if x < 0.0 || y < 0.0 || x >= img.width() as f32 || y >= img.height() as f32 {
+                continue;
+            }
+            let pixel = img.get_pixel(x as u32, y as u32);
+            for c in 0..3 {
+                sum[c] += pixel[c] as u32;
+            }
+            count += 1;
+        }
+
+        if count == 0 {
+            return None;
+        }
+        Some(sum.map(|s| (s / count) as u8))
+    }
+
+    // Bounding box of the bar with a small margin around it
+    pub fn rect(&self) -> Rect {
+        let margin = self.width / 2 + 2;
+        let x0 = self.min.0.min(self.max.0).saturating_sub(margin);
+        let y0 = self.min.1.min(self.max.1).saturating_sub(margin);
+        let x1 = self.min.0.max(self.max.0) + margin;
+        let y1 = self.min.1.max(self.max.1) + margin;
+        (x0, y0, x1 - x0 + 1, y1 - y0 + 1)
+    }
+}
+
+/// How colors of an image map to temperatures: either the scale bar printed
+/// in the image or a palette, stretched over `min..=max`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Calibration {
+    pub bar: Bar,
+    pub palette: Option<Palette>,
+    pub min: f32,
+    pub max: f32,
+    pub step: f32,
+    pub lookup: Lookup,
+    pub min_reading: Option<Reading>,
+    pub max_reading: Option<Reading>,
+}
+
+impl Default for Calibration {
+    fn default() -> Self {
+        Calibration {
+            bar: Bar::default(),
+            palette: None,
+            min: 10.0,
+            max: 30.0,
+            step: 0.1,
+            lookup: Lookup::default(),
+            min_reading: None,
+            max_reading: None,
+        }
+    }
+}
+
+impl Calibration {
+    /// Finds the scale bar and reads the labels at its ends. Returns `None`
+    /// when the image has no recognizable bar.
+    pub fn detect(img: &RgbaImage, recognizer: &Recognizer) -> Option<Calibration> {
+        let bar = detect_bar(img)?;
+        let (max_reading, min_reading) = recognizer.read_bar_labels(img, &bar);
+        let mut calibration = Calibration {
+            bar,
+            min_reading,
+            max_reading,
+            ..Default::default()
+        };
+        if let (Some(lo), Some(hi)) = (min_reading, max_reading)
+            && lo.value < hi.value
+        {
+            calibration.min = lo.value;
+            calibration.max = hi.value;
+        }
+        Some(calibration)
+    }
+
+    /// Colors and their temperatures, sampled every `step` degrees
+    pub fn color_map(&self, img: &RgbaImage) -> Map<[u8; 3], f32> {
+        match &self.palette {
+            Some(palette) => palette.to_map(self.min, self.max, self.step),
+            None => extract_color_to_temp_map(img, &self.bar, self.min, self.max, self.step),
+        }
+    }
+
+    /// Regions that show the legend rather than the scene: the bar and its labels
+    pub fn excluded(&self) -> Vec<Rect> {
+        if self.palette.is_some() {
+            return Vec::new();
+        }
+        let mut excluded = vec![self.bar.rect()];
+        excluded.extend(
+            [self.min_reading, self.max_reading]
+                .into_iter()
+                .flatten()
+                .map(|r| r.rect),
+        );
+        excluded
+    }
+
+    /// Temperature of every pixel of `img`
+    pub fn apply(&self, img: &RgbaImage) -> TemperatureField {
+        let index = self.color_map(img).index();
+        TemperatureField::from_image(img, &index, self.lookup, &self.excluded())
+    }
+}
+
+pub fn extract_color_to_temp_map(
+    img: &RgbaImage,
+    bar: &Bar,
+    min_temp: f32,
+    max_temp: f32,
+    step: f32,
+) -> Map<[u8; 3], f32> {
+    let mut map = Map::new();
+    if step <= 0.0 || max_temp <= min_temp {
+        return map;
+    }
+
+    let steps = ((max_temp - min_temp) / step).round() as u32;
+    for i in 0..=steps {
+        let t = i as f32 / steps.max(1) as f32;
+        if let Some(rgb) = bar.sample(img, t) {
+            map.push(rgb, min_temp + (max_temp - min_temp) * t);
+        }
+    }
+
+    map
+}
+
+const MIN_SATURATION: i32 = 40;
+const MAX_ACROSS_DIST: i32 = 24;
+const MAX_ALONG_DIST: i32 = 48;
+const MIN_SPAN_DIST: i32 = 120;
+const FLAT_DIST: i32 = 4;
+const MAX_LUMINANCE_DROP: f32 = 2.0;
+
+fn dist(a: [u8; 3], b: [u8; 3]) -> i32 {
+    (0..3).map(|c| (a[c] as i32 - b[c] as i32).abs()).sum()
+}
+
+fn saturation(p: [u8; 3]) -> i32 {
+    *p.iter().max().unwrap() as i32 - *p.iter().min().unwrap() as i32
+}
+
+fn luminance(p: [u8; 3]) -> f32 {
+    0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32
+}
+
+struct Strip {
+    across: u32,
+    start: u32,
+    end: u32,
+    width: u32,
+}
+
+// Finds the longest narrow run along `along` that is uniform across, changes
+// smoothly and is mostly saturated or, for gray bars, gets brighter one way.
+// Flat stretches at either end of a run are background touching the bar, so
+// a run only counts as far as its colors keep changing.
+fn scan_strips(across: u32, along: u32, get: impl Fn(u32, u32) -> [u8; 3]) -> Option<Strip> {
+    let min_len = (along / 6).max(32);
+    let uniform = |v: u32, u: u32| {
+        let p = get(v, u);
+        dist(get(v - 1, u), p) <= MAX_ACROSS_DIST && dist(get(v + 1, u), p) <= MAX_ACROSS_DIST
+    };
+
+    // Neighbouring lines that match the run in the middle, as long as the bar
+    // they make has sharp edges along most of its length. Smooth parts of the
+    // scene fade out to the sides instead.
+    let widen = |v: u32, start: u32, end: u32| {
+        let mid = (start + end) / 2;
+        let center = get(v, mid);
+        let (mut lo, mut hi) = (v, v);
+        while lo > 0 && dist(get(lo - 1, mid), center) <= MAX_ACROSS_DIST {
+            lo -= 1;
+        }
+        while hi + 1 < across && dist(get(hi + 1, mid), center) <= MAX_ACROSS_DIST {
+            hi += 1;
+        }
+        if hi - lo + 1 > across / 10 {
+            return None;
+        }
+        let edge = |inside: u32, outside: Option<u32>| {
+            let sharp = (start..=end)
+                .filter(|&u| {
+                    outside.is_some_and(|o| dist(get(o, u), get(inside, u)) > MAX_ALONG_DIST)
+                })
+                .count() as u32;
+            sharp * 2 > end - start + 1
+        };
+        (edge(lo, lo.checked_sub(1)) && edge(hi, Some(hi + 1).filter(|&o| o < across)))
+            .then_some((lo, hi))
+    };
+
+    let mut best: Option<Strip> = None;
+    for v in 1..across.saturating_sub(1) {
+        let mut u = 0;
+        while u < along {
+            if !uniform(v, u) {
+                u += 1;
+                continue;
+            }
+
+            let run = u;
+            u += 1;
+            while u < along && uniform(v, u) && dist(get(v, u - 1), get(v, u)) <= MAX_ALONG_DIST {
+                u += 1;
+            }
+
+            let (mut start, mut end) = (run, u - 1);
+            let (first, last) = (get(v, start), get(v, end));
+            while start < end && dist(get(v, start + 1), first) <= FLAT_DIST {
+                start += 1;
+            }
+            while end > start && dist(get(v, end - 1), last) <= FLAT_DIST {
+                end -= 1;
+            }
+
+            let len = end - start + 1;
+            if len < min_len
+                || dist(first, last) < MIN_SPAN_DIST
+                || best.as_ref().is_some_and(|b| len <= b.end - b.start + 1)
+            {
+                continue;
+            }
+            let saturated = (start..=end)
+                .filter(|&u| saturation(get(v, u)) >= MIN_SATURATION)
+                .count() as u32;
+            let dir = (luminance(last) - luminance(first)).signum();
+            let monotonic = (start..end).all(|u| {
+                (luminance(get(v, u + 1)) - luminance(get(v, u))) * dir >= -MAX_LUMINANCE_DROP
+            });
+            if (saturated * 2 >= len || monotonic)
+                && let Some((lo, hi)) = widen(v, start, end)
+            {
+                best = Some(Strip {
+                    across: (lo + hi) / 2,
+                    start,
+                    end,
+                    width: hi - lo + 1,
+                });
+            }
+        }
+    }
+
+    let mut strip = best?;
+    if strip.end - strip.start > 4 {
+        strip.start += 1;
+        strip.end -= 1;
+    }
+    Some(strip)
+}
+
+pub fn detect_bar(img: &RgbaImage) -> Option<Bar> {
+    let (w, h) = img.dimensions();
+    let rgb = |x: u32, y: u32| {
+        let p = img.get_pixel(x, y);
+        [p[0], p[1], p[2]]
+    };
+
+    let vertical =
+        scan_strips(w, h, rgb).map(|s| Bar::new((s.across, s.start), (s.across, s.end), s.width));
+    let horizontal = scan_strips(h, w, |y, x| rgb(x, y))
+        .map(|s| Bar::new((s.start, s.across), (s.end, s.across), s.width));
+
+    let bar = match (vertical, horizontal) {
+        (Some(v), Some(h)) => {
+            if v.length() >= h.length() {
+                v
+            } else {
+                h
+            }
+        }
+        (v, h) => v.or(h)?,
+    };
+
+    // Hot ends are brighter in every common palette
+    let max = luminance(bar.sample(img, 1.0)?);
+    let min = luminance(bar.sample(img, 0.0)?);
+    if max >= min {
+        Some(bar)
+    } else {
+        Some(Bar::new(bar.min, bar.max, bar.width))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    // A noisy 320x240 scene with `palette` drawn as a 5 pixel wide bar at
+    // x=290, hot at y=60 and cold at y=190
+    fn scene(palette: Option<&Palette>) -> RgbaImage {
+        let mut seed = 12345u32;
+        let mut img = RgbaImage::from_fn(320, 240, |_, _| {
+            seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+            let [r, g, b, _] = (seed >> 8).to_le_bytes();
+            Rgba([r, g, b, 255])
+        });
+        if let Some(palette) = palette {
+            for y in 60..=190 {
+                let [r, g, b] = palette.color_at((190 - y) as f32 / 130.0);
+                for x in 288..=292 {
+                    img.put_pixel(x, y, Rgba([r, g, b, 255]));
+                }
+            }
+        }
+        img
+    }
+
+    #[test]
+    fn detects_bars_of_every_palette() {
+        let mut palettes = Palette::builtin();
+        palettes.push(Palette::new(
+            "Blue to red",
+            &[
+                [0, 0, 255],
+                [0, 255, 255],
+                [0, 255, 0],
+                [255, 255, 0],
+                [255, 0, 0],
+            ],
+        ));
+        for palette in &palettes {
+            let bar = detect_bar(&scene(Some(palette)));
+            assert_eq!(
+                bar,
+                Some(Bar::new((290, 61), (290, 189), 5)),
+                "{}",
+                palette.name
+            );
+        }
+    }
+
+    #[test]
+    fn finds_no_bar_in_noise() {
+        assert_eq!(detect_bar(&scene(None)), None);
+    }
+}
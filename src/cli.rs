@@ -0,0 +1,303 @@
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+
+use thermal_maps::{
+    Bar, Filter, ImageData, Radiometric, View, export,
+    image::list_images,
+    ocr::{Reading, Recognizer, TEMPLATES_DIR},
+    palette::{PALETTES_DIR, Palette},
+    project::Project,
+    radiometric::{Camera, Scale},
+};
+
+const USAGE: &str = "\
+Usage: thermal-maps [<command> <folder> [options]]
+
+Without a command the viewer window opens. Edits saved by the viewer in the
+folder's project file are applied before the options below.
+
+Commands:
+  extract   Print the scale bar, range and palette coverage of every image
+  filter    Gray out pixels outside the filter range and save the images
+  export    Save temperature grids of every image
+  stats     Print temperature statistics of every image
+
+Options:
+  --palette <name>        Use a palette instead of the scale bar
+  --scale <min>:<max>     Temperature range of the palette
+  --step <step>           Temperature step between palette samples
+  --counts centikelvin|<gain>:<offset>
+                          Temperatures of 16-bit grayscale frames
+  --bar <x>,<y>,<x>,<y>[,<width>]
+                          Scale bar from its hot end to its cold end
+  --filter-min <temp>     Lower bound for filter
+  --filter-max <temp>     Upper bound for filter
+  --format <ext>          csv, summary.csv, tif, npy or png for export (csv)
+  --out <dir>             Output directory (<folder>/filtered or <folder>/export)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Command {
+    Extract,
+    Filter,
+    Export,
+    Stats,
+}
+
+#[derive(Debug, Default)]
+struct Options {
+    palette: Option<String>,
+    scale: Option<(f32, f32)>,
+    step: Option<f32>,
+    counts: Option<Scale>,
+    bar: Option<Bar>,
+    filter_min: Option<f32>,
+    filter_max: Option<f32>,
+    format: Option<String>,
+    out: Option<PathBuf>,
+}
+
+pub fn run(args: &[String]) -> Result<()> {
+    let Some(command) = args.first() else {
+        bail!("{USAGE}");
+    };
+    let command = match command.as_str() {
+        "extract" => Command::Extract,
+        "filter" => Command::Filter,
+        "export" => Command::Export,
+        "stats" => Command::Stats,
+        "help" | "-h" | "--help" => {
+            println!("{USAGE}");
+            return Ok(());
+        }
+        other => bail!("Unknown command {other:?}\n\n{USAGE}"),
+    };
+    let Some(folder) = args.get(1) else {
+        bail!("Missing folder\n\n{USAGE}");
+    };
+    let folder = PathBuf::from(folder);
+    let options = parse_options(&args[2..])?;
+
+    let palette = match &options.palette {
+        Some(name) => Some(
+            Palette::library(Path::new(PALETTES_DIR))
+                .into_iter()
+                .find(|p| p.name.eq_ignore_ascii_case(name))
+                .context(format!("Unknown palette {name:?}"))?,
+        ),
+        None => None,
+    };
+    let recognizer = Recognizer::load(Path::new(TEMPLATES_DIR));
+    let project = Project::load(&folder)?.unwrap_or_default();
+    let folder_bar = options.bar.or(project.folder_bar);
+
+    let out = match command {
+        Command::Filter => Some(options.out.clone().unwrap_or(folder.join("filtered"))),
+        Command::Export => Some(options.out.clone().unwrap_or(folder.join("export"))),
+        _ => None,
+    };
+    if let Some(out) = &out {
+        std::fs::create_dir_all(out).context(format!("Failed to create {}", out.display()))?;
+    }
+
+    if command == Command::Stats {
+        println!("image,pixels,min,max,mean,std_dev");
+    }
+
+    // One bad image should not hold up the rest of the folder
+    let mut failed = 0;
+    let mut counted = false;
+    for path in list_images(&folder)? {
+        let mut d = match ImageData::open(&path, &recognizer, folder_bar) {
+            Ok(d) => d,
+            Err(e) => {
+                eprintln!("{e:#}");
+                failed += 1;
+                continue;
+            }
+        };
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let mut view = View::new(&d);
+        if let Some(saved) = project.images.get(name.as_ref()) {
+            d.apply_project(saved, folder_bar);
+            view.apply_project(saved);
+        }
+        if let Some(scale) = options.counts
+            && let Some(Radiometric {
+                camera: Camera::Counts(s),
+                ..
+            }) = &mut d.radiometric
+        {
+            *s = scale;
+            d.extract(folder_bar);
+            d.fit_range();
+            counted = true;
+        }
+        if palette.is_some() || options.scale.is_some() || options.step.is_some() {
+            if let Some((min, max)) = options.scale {
+                d.min = min;
+                d.max = max;
+            }
+            if let Some(step) = options.step {
+                d.step = step;
+            }
+            if palette.is_some() {
+                d.palette = palette.clone();
+            }
+            d.extract(folder_bar);
+        }
+
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let written = match command {
+            Command::Extract => {
+                print_calibration(&name, &d, folder_bar);
+                None
+            }
+            Command::Filter => {
+                d.filter = Filter {
+                    min: options.filter_min,
+                    max: options.filter_max,
+                };
+                let target = out.as_ref().unwrap().join(format!("{stem}.png"));
+                let saved = d
+                    .filtered()
+                    .save(&target)
+                    .context(format!("Failed to save {}", target.display()));
+                Some(saved.map(|()| target))
+            }
+            Command::Export => {
+                let ext = options.format.as_deref().unwrap_or("csv");
+                let target = out.as_ref().unwrap().join(format!("{stem}.{ext}"));
+                Some(export::save(&target, &d, &view.layers).map(|()| target))
+            }
+            Command::Stats => {
+                match d.temperatures.stats() {
+                    Some(s) => println!(
+                        "{name},{},{:.3},{:.3},{:.3},{:.3}",
+                        s.count, s.min, s.max, s.mean, s.std_dev
+                    ),
+                    None => println!("{name},0,,,,"),
+                }
+                None
+            }
+        };
+        match written {
+            Some(Ok(target)) => println!("{name} -> {}", target.display()),
+            Some(Err(e)) => {
+                eprintln!("{e:#}");
+                failed += 1;
+            }
+            None => {}
+        }
+    }
+
+    if options.counts.is_some() && !counted {
+        eprintln!("Warning: --counts only applies to 16-bit grayscale frames, there were none");
+    }
+    if failed > 0 {
+        bail!("Failed to process {failed} of the images");
+    }
+    Ok(())
+}
+
+fn parse_options(args: &[String]) -> Result<Options> {
+    let mut options = Options::default();
+    let mut args = args.iter();
+    while let Some(flag) = args.next() {
+        let mut value = || {
+            args.next()
+                .map(String::as_str)
+                .context(format!("Missing value for {flag}"))
+        };
+        match flag.as_str() {
+            "--palette" => options.palette = Some(value()?.to_string()),
+            "--scale" => {
+                let v = value()?;
+                let (min, max) = v.split_once(':').context("Expected --scale <min>:<max>")?;
+                options.scale = Some((parse_number(min)?, parse_number(max)?));
+            }
+            "--step" => options.step = Some(parse_number(value()?)?),
+            "--counts" => {
+                let v = value()?;
+                options.counts = Some(if v.eq_ignore_ascii_case("centikelvin") {
+                    Scale::Centikelvin
+                } else {
+                    let (gain, offset) = v
+                        .split_once(':')
+                        .context("Expected --counts centikelvin|<gain>:<offset>")?;
+                    Scale::Linear {
+                        gain: parse_number(gain)?,
+                        offset: parse_number(offset)?,
+                    }
+                });
+            }
+            "--bar" => {
+                let parts = value()?
+                    .split(',')
+                    .map(|p| p.trim().parse::<u32>())
+                    .collect::<Result<Vec<_>, _>>()
+                    .context("Expected --bar <x>,<y>,<x>,<y>[,<width>]")?;
+                options.bar = match parts[..] {
+                    [x0, y0, x1, y1] => Some(Bar::new((x0, y0), (x1, y1), 1)),
+                    [x0, y0, x1, y1, w] => Some(Bar::new((x0, y0), (x1, y1), w)),
+                    _ => bail!("Expected --bar <x>,<y>,<x>,<y>[,<width>]"),
+                };
+            }
+            "--filter-min" => options.filter_min = Some(parse_number(value()?)?),
+            "--filter-max" => options.filter_max = Some(parse_number(value()?)?),
+            "--format" => options.format = Some(value()?.trim_start_matches('.').to_string()),
+            "--out" => options.out = Some(PathBuf::from(value()?)),
+            other => bail!("Unknown option {other:?}\n\n{USAGE}"),
+        }
+    }
+    Ok(options)
+}
+
+fn parse_number(s: &str) -> Result<f32> {
+    s.trim()
+        .parse()
+        .context(format!("Expected a number, got {s:?}"))
+}
+
+fn print_calibration(name: &str, d: &ImageData, folder_bar: Option<Bar>) {
+    let source = if let Some(r) = &d.radiometric {
+        let parameters = match r.camera {
+            Camera::Flir(_) | Camera::Dji(_) => {
+                format!("emissivity {:.2}", r.camera.emissivity())
+            }
+            Camera::Counts(Scale::Centikelvin) => "centikelvin".to_string(),
+            Camera::Counts(Scale::Linear { gain, offset }) => {
+                format!("gain {gain}, offset {offset}")
+            }
+        };
+        format!(
+            "{} sensor data {}x{}, {parameters}",
+            r.camera.name(),
+            r.width,
+            r.height
+        )
+    } else {
+        match (&d.palette, d.bar.or(folder_bar), d.detected_bar) {
+            (Some(p), _, _) => format!("palette {}", p.name),
+            (None, Some(b), _) | (None, None, Some(b)) => format!(
+                "bar {},{} -> {},{} width {}",
+                b.max.0, b.max.1, b.min.0, b.min.1, b.width
+            ),
+            (None, None, None) => "default bar".to_string(),
+        }
+    };
+    let confidence = |r: Option<Reading>| {
+        r.map_or("manual".to_string(), |r| {
+            format!("read {:.0}%", r.confidence * 100.0)
+        })
+    };
+    let valid = d.temperatures.temperatures().count();
+    let coverage = valid as f32 / d.temperatures.values.len().max(1) as f32 * 100.0;
+    println!(
+        "{name}: {source}, {:.2}..{:.2} °C (min {}, max {}), {} samples, {coverage:.1}% of pixels matched",
+        d.min,
+        d.max,
+        confidence(d.min_reading),
+        confidence(d.max_reading),
+        d.color_temp.len(),
+    );
+}
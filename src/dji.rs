@@ -0,0 +1,212 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    flir::{ATMOSPHERE, transmission},
+    jpeg,
+    radiometric::{Camera, KELVIN, Radiometric},
+};
+
+const APP3: u8 = jpeg::APP0 + 3;
+const APP4: u8 = jpeg::APP0 + 4;
+
+// Raw values are taken as 1/64 K of apparent temperature. DJI does not
+// document the format, which its Thermal SDK reads, so this and the correction
+// below are an approximation that has not been checked against that SDK.
+const COUNTS_PER_KELVIN: f32 = 64.0;
+
+// Start of the parameters of the H20T, which are integers
+const H20T_MAGIC: [u8; 4] = [0xaa, 0x55, 0x38, 0x00];
+
+/// Measurement parameters of a DJI thermal camera, temperatures in °C
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Parameters {
+    pub emissivity: f32,
+    // Meters
+    pub object_distance: f32,
+    pub reflected_temperature: f32,
+    pub ambient_temperature: f32,
+    // 0 to 1
+    pub relative_humidity: f32,
+}
+
+// What DJI's own tools assume for images without parameters
+impl Default for Parameters {
+    fn default() -> Parameters {
+        Parameters {
+            emissivity: 1.0,
+            object_distance: 5.0,
+            reflected_temperature: 23.0,
+            ambient_temperature: 25.0,
+            relative_humidity: 0.7,
+        }
+    }
+}
+
+impl Parameters {
+    /// Approximate object temperature for a raw value, taking out reflections
+    /// and what the atmosphere adds to it. Radiance is taken to go with the
+    /// fourth power of temperature and the atmosphere to absorb like it does
+    /// in FLIR's model, DJI publishing neither.
+    pub fn converter(&self) -> impl Fn(u16) -> f32 + '_ {
+        let radiance = |t: f32| (t + KELVIN).powi(4);
+        let emissivity = self.emissivity.max(0.001);
+        let tau = transmission(
+            self.object_distance,
+            self.relative_humidity,
+            self.ambient_temperature,
+            ATMOSPHERE,
+        )
+        .max(0.001);
+
+        let reflected = (1.0 - emissivity) * tau * radiance(self.reflected_temperature);
+        let atmosphere = (1.0 - tau) * radiance(self.ambient_temperature);
+        let scale = emissivity * tau;
+
+        move |raw| {
+            let apparent = raw as f32 / COUNTS_PER_KELVIN;
+            ((apparent.powi(4) - reflected - atmosphere) / scale).powf(0.25) - KELVIN
+        }
+    }
+}
+
+/// Sensor data in the APP3 segments of a DJI JPEG of `width` x `height`, None
+/// when there is none or it does not cover the image
+pub fn read(bytes: &[u8], width: u32, height: u32) -> Option<Radiometric> {
+    let segments = jpeg::segments(bytes);
+    let data: Vec<u8> = segments
+        .iter()
+        .filter(|(marker, _)| *marker == APP3)
+        .flat_map(|(_, payload)| payload.iter().copied())
+        .collect();
+    if data.is_empty() || data.len() != (width * height * 2) as usize {
+        return None;
+    }
+
+    let raw = data
+        .chunks_exact(2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .collect();
+    let parameters = segments
+        .iter()
+        .filter(|(marker, _)| *marker == APP4)
+        .find_map(|(_, payload)| parameters(payload))
+        .unwrap_or_default();
+    Some(Radiometric {
+        width,
+        height,
+        raw,
+        camera: Camera::Dji(parameters),
+    })
+}
+
+// The H20T stores integers after its magic, later cameras floats from the start
+fn parameters(app4: &[u8]) -> Option<Parameters> {
+    let u16_at = |offset: usize| {
+        let b = app4.get(offset..offset + 2)?;
+        Some(u16::from_le_bytes([b[0], b[1]]) as f32)
+    };
+    let f32_at = |offset: usize| {
+        let b = app4.get(offset..offset + 4)?;
+        Some(f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+
+    let p = if app4.starts_with(&H20T_MAGIC) {
+        Parameters {
+            object_distance: u16_at(0x44)?,
+            relative_humidity: u16_at(0x46)? / 100.0,
+            emissivity: u16_at(0x48)? / 100.0,
+            reflected_temperature: u16_at(0x4a)?,
+            ambient_temperature: u16_at(0x4c)?,
+        }
+    } else {
+        Parameters {
+            ambient_temperature: f32_at(0x00)?,
+            object_distance: f32_at(0x04)?,
+            emissivity: f32_at(0x08)?,
+            relative_humidity: f32_at(0x0c)?,
+            reflected_temperature: f32_at(0x10)?,
+        }
+    };
+    // Anything else in APP4 gives nonsense
+    let valid = (0.01..=1.0).contains(&p.emissivity)
+        && (0.0..=1.0).contains(&p.relative_humidity)
+        && (0.0..=1000.0).contains(&p.object_distance)
+        && (-100.0..=1000.0).contains(&p.reflected_temperature)
+        && (-100.0..=200.0).contains(&p.ambient_temperature);
+    valid.then_some(p)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn float_parameters() {
+        let app4: Vec<u8> = [25.0f32, 10.0, 0.95, 0.6, 20.0, 0.0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let p = parameters(&app4).unwrap();
+        assert_eq!(
+            p,
+            Parameters {
+                emissivity: 0.95,
+                object_distance: 10.0,
+                reflected_temperature: 20.0,
+                ambient_temperature: 25.0,
+                relative_humidity: 0.6,
+            }
+        );
+    }
+
+    #[test]
+    fn h20t_parameters() {
+        let mut app4 = vec![0; 0x50];
+        app4[..4].copy_from_slice(&H20T_MAGIC);
+        for (i, v) in [10u16, 60, 95, 20, 25].into_iter().enumerate() {
+            app4[0x44 + i * 2..0x46 + i * 2].copy_from_slice(&v.to_le_bytes());
+        }
+        let p = parameters(&app4).unwrap();
+        assert_eq!(p.object_distance, 10.0);
+        assert_eq!(p.relative_humidity, 0.6);
+        assert_eq!(p.emissivity, 0.95);
+        assert_eq!(p.reflected_temperature, 20.0);
+        assert_eq!(p.ambient_temperature, 25.0);
+
+        // Cut short before its parameters
+        assert_eq!(parameters(&app4[..0x48]), None);
+    }
+
+    #[test]
+    fn other_app4_data_is_ignored() {
+        assert_eq!(parameters(&[]), None);
+        assert_eq!(parameters(&[0; 0x20]), None);
+        assert_eq!(parameters(b"Exif\0\0some other metadata"), None);
+    }
+
+    #[test]
+    fn black_body_reads_its_apparent_temperature() {
+        let p = Parameters {
+            emissivity: 1.0,
+            object_distance: 0.0,
+            ..Parameters::default()
+        };
+        let convert = p.converter();
+        for t in [-20.0f32, 0.0, 36.6, 150.0] {
+            let raw = ((t + KELVIN) * COUNTS_PER_KELVIN).round() as u16;
+            assert!(
+                (convert(raw) - t).abs() < 0.02,
+                "{} is not {t}",
+                convert(raw)
+            );
+        }
+
+        // Reflections of a colder room make a less emissive object read warmer
+        let p = Parameters {
+            emissivity: 0.8,
+            ..p
+        };
+        let raw = ((40.0 + KELVIN) * COUNTS_PER_KELVIN) as u16;
+        assert!(p.converter()(raw) > 40.0);
+    }
+}
@@ -0,0 +1,270 @@
+use anyhow::{Context, Result};
+use std::{
+    fs::File,
+    io::{BufWriter, Write},
+    path::Path,
+};
+
+use tiff::{
+    encoder::{TiffEncoder, colortype::Gray32Float},
+    tags::Tag,
+};
+
+use crate::{image::ImageData, render::Operation, stats::Stats};
+
+const GDAL_METADATA: u16 = 42112;
+const GDAL_NODATA: u16 = 42113;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Image,
+    Csv,
+    SummaryCsv,
+    Tiff,
+    Npy,
+}
+
+impl Format {
+    pub fn from_path(path: &Path) -> Format {
+        let name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_lowercase();
+        if name.ends_with(".summary.csv") {
+            Format::SummaryCsv
+        } else if name.ends_with(".csv") {
+            Format::Csv
+        } else if name.ends_with(".tif") || name.ends_with(".tiff") {
+            Format::Tiff
+        } else if name.ends_with(".npy") {
+            Format::Npy
+        } else {
+            Format::Image
+        }
+    }
+}
+
+// Images are saved with `layers` applied, everything else as temperatures
+pub fn save(path: &Path, d: &ImageData, layers: &[Operation]) -> Result<()> {
+    match Format::from_path(path) {
+        Format::Image => d.render(layers).save(path).context("Failed to save image"),
+        Format::Csv => write_csv(path, d),
+        Format::SummaryCsv => write_summary_csv(path, d),
+        Format::Tiff => write_tiff(path, d),
+        Format::Npy => write_npy(path, d),
+    }
+}
+
+// Row-major temperatures in °C, NaN where there is none
+fn grid(d: &ImageData) -> Vec<f32> {
+    let (width, height) = d.raw_image.dimensions();
+    if d.temperatures.values.len() == (width * height) as usize {
+        d.temperatures.values.clone()
+    } else {
+        vec![f32::NAN; (width * height) as usize]
+    }
+}
+
+// Single-channel 32-bit float TIFF. The range and unit go both into the image
+// description and into GDAL metadata tags so GIS tools pick them up.
+pub fn write_tiff(path: &Path, d: &ImageData) -> Result<()> {
+    let (width, height) = d.raw_image.dimensions();
+    let data = grid(d);
+    let stats = Stats::from_values(data.iter().copied());
+    let (min, max) = stats.map_or((f32::NAN, f32::NAN), |s| (s.min, s.max));
+
+    let description = format!("temperature; unit=degC; min={min:.3}; max={max:.3}");
+    let metadata = format!(
+        "<GDALMetadata><Item name=\"min\">{min:.3}</Item><Item name=\"max\">{max:.3}</Item>\
+         <Item name=\"unit\">degC</Item><Item name=\"UNITTYPE\" sample=\"0\" role=\"unittype\">degC</Item>\
+         </GDALMetadata>"
+    );
+
+    let file = File::create(path).context(format!("Failed to create {}", path.display()))?;
+    let mut encoder = TiffEncoder::new(BufWriter::new(file)).context("Failed to start TIFF")?;
+    let mut image = encoder
+        .new_image::<Gray32Float>(width, height)
+        .context("Failed to start TIFF image")?;
+    image
+        .encoder()
+        .write_tag(Tag::ImageDescription, description.as_str())?;
+    image
+        .encoder()
+        .write_tag(Tag::Unknown(GDAL_METADATA), metadata.as_str())?;
+    image
+        .encoder()
+        .write_tag(Tag::Unknown(GDAL_NODATA), "nan")?;
+    image.write_data(&data).context("Failed to write TIFF")
+}
+
+// NumPy .npy version 1.0, a (rows, cols) little-endian float32 array
+pub fn write_npy(path: &Path, d: &ImageData) -> Result<()> {
+    let (width, height) = d.raw_image.dimensions();
+    let mut header =
+        format!("{{'descr': '<f4', 'fortran_order': False, 'shape': ({height}, {width}), }}");
+    // Magic, version and header length take 10 bytes, the data starts 64-byte aligned
+    while (10 + header.len() + 1) % 64 != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+
+    let file = File::create(path).context(format!("Failed to create {}", path.display()))?;
+    let mut w = BufWriter::new(file);
+    w.write_all(b"\x93NUMPY\x01\x00")?;
+    w.write_all(&(header.len() as u16).to_le_bytes())?;
+    w.write_all(header.as_bytes())?;
+    for t in grid(d) {
+        w.write_all(&t.to_le_bytes())?;
+    }
+    w.flush().context("Failed to write NPY")
+}
+
+// One line per pixel, with an empty temperature where there is none
+pub fn write_csv(path: &Path, d: &ImageData) -> Result<()> {
+    let file = File::create(path).context(format!("Failed to create {}", path.display()))?;
+    let mut w = BufWriter::new(file);
+    writeln!(w, "row,col,temperature")?;
+    for y in 0..d.raw_image.height() {
+        for x in 0..d.raw_image.width() {
+            match d.temperature_at(x, y) {
+                Some(t) => writeln!(w, "{y},{x},{t:.3}")?,
+                None => writeln!(w, "{y},{x},")?,
+            }
+        }
+    }
+    w.flush().context("Failed to write CSV")
+}
+
+pub fn write_summary_csv(path: &Path, d: &ImageData) -> Result<()> {
+    let file = File::create(path).context(format!("Failed to create {}", path.display()))?;
+    let mut w = BufWriter::new(file);
+    writeln!(w, "region,pixels,min,max,mean,std_dev")?;
+    for (name, stats) in summary(d) {
+        let name = csv_field(&name);
+        match stats {
+            Some(s) => writeln!(
+                w,
+                "{name},{},{:.3},{:.3},{:.3},{:.3}",
+                s.count, s.min, s.max, s.mean, s.std_dev
+            )?,
+            None => writeln!(w, "{name},0,,,,")?,
+        }
+    }
+    w.flush().context("Failed to write CSV")
+}
+
+fn summary(d: &ImageData) -> Vec<(String, Option<Stats>)> {
+    let mut regions = vec![("image".to_string(), d.temperatures.stats())];
+    let filter = d.filter;
+    if filter.is_active() {
+        regions.push((
+            "filter".to_string(),
+            Stats::from_values(
+                d.temperatures
+                    .temperatures()
+                    .filter(|t| filter.contains(*t)),
+            ),
+        ));
+    }
+    for roi in &d.rois {
+        regions.push((roi.name.clone(), roi.stats(&d.temperatures)));
+    }
+    for spot in &d.spots {
+        regions.push((
+            spot.name.clone(),
+            Stats::from_values(spot.temperature(&d.temperatures)),
+        ));
+    }
+    regions
+}
+
+// Quotes names that would otherwise break the row
+fn csv_field(s: &str) -> String {
+    if s.contains([',', '"', '\n']) {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ocr::Recognizer;
+    use image::{ImageBuffer, Luma};
+    use tiff::decoder::{Decoder, DecodingResult};
+
+    // A 5x3 16-bit frame from 20 °C up in steps of 0.1 °C, opened from `dir`
+    fn frame(dir: &Path) -> ImageData {
+        let path = dir.join("frame.png");
+        ImageBuffer::<Luma<u16>, _>::from_fn(5, 3, |x, y| Luma([29315 + (y * 5 + x) as u16 * 10]))
+            .save(&path)
+            .unwrap();
+        ImageData::open(&path, &Recognizer::builtin(), None).unwrap()
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("thermal-maps-{name}-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn npy_has_an_aligned_header_and_the_grid() {
+        let dir = temp_dir("npy");
+        let d = frame(&dir);
+        let path = dir.join("frame.npy");
+        write_npy(&path, &d).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + len) % 64, 0);
+        let header = std::str::from_utf8(&bytes[10..10 + len]).unwrap();
+        assert!(header.starts_with("{'descr': '<f4', 'fortran_order': False, 'shape': (3, 5), }"));
+        assert!(header.ends_with('\n'));
+
+        let values: Vec<f32> = bytes[10 + len..]
+            .chunks(4)
+            .map(|c| f32::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        assert_eq!(values, d.temperatures.values);
+        assert!((values[14] - 21.4).abs() < 1e-3);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn tiff_carries_the_range_and_unit() {
+        let dir = temp_dir("tiff");
+        let d = frame(&dir);
+        let path = dir.join("frame.tif");
+        write_tiff(&path, &d).unwrap();
+
+        let mut decoder = Decoder::new(File::open(&path).unwrap()).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (5, 3));
+        assert_eq!(
+            decoder.get_tag_ascii_string(Tag::ImageDescription).unwrap(),
+            "temperature; unit=degC; min=20.000; max=21.400"
+        );
+        // Tags are looked up by number, the decoder names some GDAL ones
+        let metadata = decoder
+            .get_tag_ascii_string(Tag::from_u16_exhaustive(GDAL_METADATA))
+            .unwrap();
+        assert!(metadata.contains("<Item name=\"max\">21.400</Item>"));
+        assert!(metadata.contains("role=\"unittype\">degC</Item>"));
+        assert_eq!(
+            decoder
+                .get_tag_ascii_string(Tag::from_u16_exhaustive(GDAL_NODATA))
+                .unwrap(),
+            "nan"
+        );
+        match decoder.read_image().unwrap() {
+            DecodingResult::F32(values) => assert_eq!(values, d.temperatures.values),
+            _ => panic!("Expected 32-bit floats"),
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
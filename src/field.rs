@@ -0,0 +1,203 @@
+use image::RgbaImage;
+
+use crate::{
+    map::{ColorIndex, Lookup},
+    ocr::Rect,
+    stats::Stats,
+};
+
+// Pixels further than this from every palette color have no temperature
+pub const MAX_PALETTE_DISTANCE: f32 = 40.0;
+
+/// Row-major temperatures in °C, NaN where a pixel has none
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TemperatureField {
+    pub width: u32,
+    pub height: u32,
+    pub values: Vec<f32>,
+}
+
+impl TemperatureField {
+    pub fn new(width: u32, height: u32) -> TemperatureField {
+        TemperatureField {
+            width,
+            height,
+            values: vec![f32::NAN; (width * height) as usize],
+        }
+    }
+
+    /// Looks up every pixel outside `excluded` in the color index
+    pub fn from_image(
+        img: &RgbaImage,
+        index: &ColorIndex<f32>,
+        lookup: Lookup,
+        excluded: &[Rect],
+    ) -> TemperatureField {
+        let (width, height) = img.dimensions();
+        let mut field = TemperatureField::new(width, height);
+        if index.is_empty() {
+            return field;
+        }
+
+        for (x, y, pixel) in img.enumerate_pixels() {
+            if excluded
+                .iter()
+                .any(|r| x >= r.0 && y >= r.1 && x < r.0 + r.2 && y < r.1 + r.3)
+            {
+                continue;
+            }
+            if let Some((temp, distance)) = index.lookup([pixel[0], pixel[1], pixel[2]], lookup)
+                && distance <= MAX_PALETTE_DISTANCE
+            {
+                field.values[(y * width + x) as usize] = temp;
+            }
+        }
+        field
+    }
+
+    // Nothing has been extracted yet
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Option<f32> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        let t = self.values[(y * self.width + x) as usize];
+        (!t.is_nan()).then_some(t)
+    }
+
+    // Every temperature, skipping pixels without one
+    pub fn temperatures(&self) -> impl Iterator<Item = f32> + '_ {
+        self.values.iter().copied().filter(|t| !t.is_nan())
+    }
+
+    // One sample per pixel of length along the line from `a` to `b`, with
+    // its distance from `a` in pixels
+    pub fn profile(&self, a: (u32, u32), b: (u32, u32)) -> Vec<(f32, Option<f32>)> {
+        let dx = b.0 as f32 - a.0 as f32;
+        let dy = b.1 as f32 - a.1 as f32;
+        let length = (dx * dx + dy * dy).sqrt();
+        let n = length.round() as u32;
+        (0..=n)
+            .map(|i| {
+                let t = if n == 0 { 0.0 } else { i as f32 / n as f32 };
+                let x = (a.0 as f32 + dx * t).round() as u32;
+                let y = (a.1 as f32 + dy * t).round() as u32;
+                (length * t, self.get(x, y))
+            })
+            .collect()
+    }
+
+    pub fn hottest(&self) -> Option<((u32, u32), f32)> {
+        self.extreme(|t, best| t > best)
+    }
+
+    pub fn coldest(&self) -> Option<((u32, u32), f32)> {
+        self.extreme(|t, best| t < best)
+    }
+
+    // First pixel whose temperature beats every other one
+    fn extreme(&self, beats: impl Fn(f32, f32) -> bool) -> Option<((u32, u32), f32)> {
+        let mut best: Option<(usize, f32)> = None;
+        for (i, &t) in self.values.iter().enumerate() {
+            if !t.is_nan() && best.is_none_or(|(_, b)| beats(t, b)) {
+                best = Some((i, t));
+            }
+        }
+        best.map(|(i, t)| {
+            let i = i as u32;
+            ((i % self.width, i / self.width), t)
+        })
+    }
+
+    // Up to `count` pixels at least as hot as `threshold` and as their
+    // neighbours, hottest first. A pixel closer than `spacing` to a hotter
+    // one that was kept is suppressed.
+    pub fn local_maxima(
+        &self,
+        count: usize,
+        threshold: f32,
+        spacing: u32,
+    ) -> Vec<((u32, u32), f32)> {
+        let mut candidates = Vec::new();
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let Some(t) = self.get(x, y) else {
+                    continue;
+                };
+                if t < threshold {
+                    continue;
+                }
+                let is_max = (-1i32..=1).all(|dy| {
+                    (-1i32..=1).all(|dx| {
+                        let (nx, ny) = (x as i32 + dx, y as i32 + dy);
+                        nx < 0 || ny < 0 || self.get(nx as u32, ny as u32).is_none_or(|n| n <= t)
+                    })
+                });
+                if is_max {
+                    candidates.push(((x, y), t));
+                }
+            }
+        }
+        candidates.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let spacing = (spacing * spacing) as i64;
+        let mut found: Vec<((u32, u32), f32)> = Vec::new();
+        for (p, t) in candidates {
+            if found.len() >= count {
+                break;
+            }
+            let far = found.iter().all(|(q, _)| {
+                let dx = p.0 as i64 - q.0 as i64;
+                let dy = p.1 as i64 - q.1 as i64;
+                dx * dx + dy * dy >= spacing
+            });
+            if far {
+                found.push((p, t));
+            }
+        }
+        found
+    }
+
+    pub fn stats(&self) -> Option<Stats> {
+        Stats::from_values(self.temperatures())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 10 °C everywhere but for a few peaks, one pixel next to them unknown
+    fn field() -> TemperatureField {
+        let mut field = TemperatureField::new(12, 8);
+        field.values.fill(10.0);
+        for ((x, y), t) in [
+            ((2, 2), 30.0),
+            ((4, 2), 28.0),
+            ((9, 5), 25.0),
+            ((6, 6), 20.0),
+        ] {
+            field.values[y * 12 + x] = t;
+        }
+        field.values[2 * 12 + 3] = f32::NAN;
+        field
+    }
+
+    #[test]
+    fn local_maxima_suppress_close_peaks() {
+        let field = field();
+        let (a, b, c, d) = (
+            ((2, 2), 30.0),
+            ((4, 2), 28.0),
+            ((9, 5), 25.0),
+            ((6, 6), 20.0),
+        );
+        assert_eq!(field.local_maxima(10, 15.0, 3), [a, c, d]);
+        assert_eq!(field.local_maxima(10, 15.0, 2), [a, b, c, d]);
+        assert_eq!(field.local_maxima(2, 15.0, 3), [a, c]);
+        assert_eq!(field.local_maxima(10, 22.0, 3), [a, c]);
+    }
+}
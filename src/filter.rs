@@ -0,0 +1,41 @@
+use image::RgbaImage;
+
+use crate::field::TemperatureField;
+
+/// Temperature range to highlight, open on the sides that are `None`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Filter {
+    pub min: Option<f32>,
+    pub max: Option<f32>,
+}
+
+impl Filter {
+    pub fn is_active(&self) -> bool {
+        self.min.is_some() || self.max.is_some()
+    }
+
+    pub fn contains(&self, temp: f32) -> bool {
+        self.min.is_none_or(|min| temp >= min) && self.max.is_none_or(|max| temp <= max)
+    }
+
+    /// Grays out every pixel whose temperature is outside the range
+    pub fn apply(&self, img: &RgbaImage, field: &TemperatureField) -> RgbaImage {
+        let mut f = img.clone();
+        for (x, y, pixel) in f.enumerate_pixels_mut() {
+            if let Some(temp) = field.get(x, y)
+                && !self.contains(temp)
+            {
+                let r = pixel[0] as u32;
+                let g = pixel[1] as u32;
+                let b = pixel[2] as u32;
+
+                let gray = (0.299 * r as f32 + 0.587 * g as f32 + 0.114 * b as f32) as u8;
+
+                pixel[0] = gray / 2 + 128;
+                pixel[1] = gray / 2 + 128;
+                pixel[2] = gray / 2 + 128;
+            }
+        }
+        f
+    }
+}
@@ -0,0 +1,524 @@
+use anyhow::{Context, Result, bail};
+use image::ImageFormat;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    jpeg,
+    radiometric::{Camera, KELVIN, Radiometric},
+};
+
+const APP1: u8 = jpeg::APP0 + 1;
+
+// Record types in the FFF directory
+const RAW_DATA: u16 = 0x01;
+const CAMERA_INFO: u16 = 0x20;
+
+// Atmospheric model constants of most FLIR cameras, alpha1, alpha2, beta1,
+// beta2 and x
+pub const ATMOSPHERE: [f32; 5] = [0.006569, 0.01262, -0.002276, -0.00667, 1.9];
+
+/// Measurement parameters and Planck constants of a FLIR camera, temperatures in °C
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraInfo {
+    pub emissivity: f32,
+    // Meters
+    pub object_distance: f32,
+    pub reflected_temperature: f32,
+    pub atmospheric_temperature: f32,
+    pub ir_window_temperature: f32,
+    pub ir_window_transmission: f32,
+    // 0 to 1
+    pub relative_humidity: f32,
+    pub planck_r1: f32,
+    pub planck_r2: f32,
+    pub planck_b: f32,
+    pub planck_f: f32,
+    pub planck_o: f32,
+    pub atmospheric_alpha1: f32,
+    pub atmospheric_alpha2: f32,
+    pub atmospheric_beta1: f32,
+    pub atmospheric_beta2: f32,
+    pub atmospheric_x: f32,
+}
+
+impl CameraInfo {
+    // Raw value a black body at `temp` would give
+    fn raw_of(&self, temp: f32) -> f32 {
+        self.planck_r1
+            / (self.planck_r2 * ((self.planck_b / (temp + KELVIN)).exp() - self.planck_f))
+            - self.planck_o
+    }
+
+    fn temperature_of(&self, raw: f32) -> f32 {
+        self.planck_b
+            / (self.planck_r1 / (self.planck_r2 * (raw + self.planck_o)) + self.planck_f).ln()
+            - KELVIN
+    }
+
+    // Through half the object distance, the IR window sitting halfway
+    fn atmospheric_transmission(&self) -> f32 {
+        transmission(
+            self.object_distance / 2.0,
+            self.relative_humidity,
+            self.atmospheric_temperature,
+            [
+                self.atmospheric_alpha1,
+                self.atmospheric_alpha2,
+                self.atmospheric_beta1,
+                self.atmospheric_beta2,
+                self.atmospheric_x,
+            ],
+        )
+    }
+
+    /// Object temperature for a raw value, taking out what the atmosphere, the IR
+    /// window and reflections add to it
+    pub fn converter(&self) -> impl Fn(u16) -> f32 + '_ {
+        let emissivity = self.emissivity.max(0.001);
+        let window = self.ir_window_transmission.max(0.001);
+        let tau = self.atmospheric_transmission().max(0.001);
+
+        let reflected = (1.0 - emissivity) / emissivity * self.raw_of(self.reflected_temperature);
+        let atmosphere = self.raw_of(self.atmospheric_temperature);
+        let near_atmosphere = (1.0 - tau) / emissivity / tau * atmosphere;
+        let far_atmosphere = (1.0 - tau) / emissivity / tau / window / tau * atmosphere;
+        let window_emission =
+            (1.0 - window) / emissivity / tau / window * self.raw_of(self.ir_window_temperature);
+        let scale = emissivity * tau * window * tau;
+        let offset = reflected + near_atmosphere + far_atmosphere + window_emission;
+
+        move |raw| self.temperature_of(raw as f32 / scale - offset)
+    }
+}
+
+/// Share of the radiation that makes it through `distance` meters of air at
+/// `temperature` °C and `humidity` from 0 to 1
+pub fn transmission(distance: f32, humidity: f32, temperature: f32, constants: [f32; 5]) -> f32 {
+    let [alpha1, alpha2, beta1, beta2, x] = constants;
+    let t = temperature;
+    let h2o =
+        humidity * (1.5587 + 0.06939 * t - 0.00027816 * t * t + 0.00000068455 * t * t * t).exp();
+    let distance = distance.max(0.0).sqrt();
+    x * (-distance * (alpha1 + beta1 * h2o.sqrt())).exp()
+        + (1.0 - x) * (-distance * (alpha2 + beta2 * h2o.sqrt())).exp()
+}
+
+/// Sensor data embedded in the APP1 segments of a FLIR JPEG, None when there is none
+pub fn read(bytes: &[u8]) -> Result<Option<Radiometric>> {
+    // Each segment starts with "FLIR\0", a version, its index and the last index
+    let mut chunks: Vec<(u8, &[u8])> = jpeg::segments(bytes)
+        .into_iter()
+        .filter(|(marker, _)| *marker == APP1)
+        .filter_map(|(_, payload)| payload.strip_prefix(b"FLIR\0"))
+        .filter(|payload| payload.len() >= 3)
+        .map(|payload| (payload[1], &payload[3..]))
+        .collect();
+    if chunks.is_empty() {
+        return Ok(None);
+    }
+    chunks.sort_by_key(|(index, _)| *index);
+    let fff: Vec<u8> = chunks
+        .into_iter()
+        .flat_map(|(_, p)| p.iter().copied())
+        .collect();
+
+    parse(&fff).map(Some)
+}
+
+fn parse(fff: &[u8]) -> Result<Radiometric> {
+    if !fff.starts_with(b"FFF\0") {
+        bail!("FLIR data does not start with an FFF header");
+    }
+    // Versions are 1xx, in whatever byte order the file is in
+    let mut header = Reader {
+        data: fff,
+        big_endian: true,
+    };
+    if !(100..200).contains(&header.u32(0x14)?) {
+        header.big_endian = false;
+    }
+
+    let directory = header.u32(0x18)? as usize;
+    let count = header.u32(0x1c)? as usize;
+    let mut raw = None;
+    let mut camera = None;
+    for i in 0..count {
+        let entry = directory + i * 0x20;
+        let kind = header.u16(entry)?;
+        let offset = header.u32(entry + 0x0c)? as usize;
+        let length = header.u32(entry + 0x10)? as usize;
+        let record = fff
+            .get(offset..offset + length)
+            .context("FLIR record is out of bounds")?;
+        match kind {
+            RAW_DATA => raw = Some(Reader::record(record)?),
+            CAMERA_INFO => camera = Some(Reader::record(record)?),
+            _ => {}
+        }
+    }
+    let raw = raw.context("FLIR data has no raw thermal image")?;
+    let camera = camera.context("FLIR data has no camera info")?;
+
+    let (width, height, values) = raw_image(&raw)?;
+    Ok(Radiometric {
+        width,
+        height,
+        raw: values,
+        camera: Camera::Flir(camera_info(&camera)?),
+    })
+}
+
+fn raw_image(r: &Reader) -> Result<(u32, u32, Vec<u16>)> {
+    let width = r.u16(0x02)? as u32;
+    let height = r.u16(0x04)? as u32;
+    let data = r.data.get(0x20..).context("FLIR raw data is truncated")?;
+
+    let values = if data.starts_with(b"\x89PNG") {
+        // FLIR writes the 16-bit samples little endian, against the PNG spec
+        image::load_from_memory_with_format(data, ImageFormat::Png)
+            .context("Failed to decode FLIR raw PNG")?
+            .into_luma16()
+            .into_raw()
+            .into_iter()
+            .map(u16::swap_bytes)
+            .collect()
+    } else if data.starts_with(b"II*\0") || data.starts_with(b"MM\0*") {
+        image::load_from_memory_with_format(data, ImageFormat::Tiff)
+            .context("Failed to decode FLIR raw TIFF")?
+            .into_luma16()
+            .into_raw()
+    } else {
+        let raw = Reader {
+            data,
+            big_endian: r.big_endian,
+        };
+        (0..(width * height) as usize)
+            .map(|i| raw.u16(i * 2))
+            .collect::<Result<_>>()?
+    };
+    if values.len() != (width * height) as usize {
+        bail!("FLIR raw data does not match its size of {width}x{height}");
+    }
+    Ok((width, height, values))
+}
+
+fn camera_info(r: &Reader) -> Result<CameraInfo> {
+    let humidity = r.f32(0x3c)?;
+    Ok(CameraInfo {
+        emissivity: r.f32(0x20)?,
+        object_distance: r.f32(0x24)?,
+        reflected_temperature: r.f32(0x28)? - KELVIN,
+        atmospheric_temperature: r.f32(0x2c)? - KELVIN,
+        ir_window_temperature: r.f32(0x30)? - KELVIN,
+        ir_window_transmission: r.f32(0x34)?,
+        // Some cameras store a percentage
+        relative_humidity: if humidity > 2.0 {
+            humidity / 100.0
+        } else {
+            humidity
+        },
+        planck_r1: r.f32(0x58)?,
+        planck_b: r.f32(0x5c)?,
+        planck_f: r.f32(0x60)?,
+        atmospheric_alpha1: r.f32(0x70)?,
+        atmospheric_alpha2: r.f32(0x74)?,
+        atmospheric_beta1: r.f32(0x78)?,
+        atmospheric_beta2: r.f32(0x7c)?,
+        atmospheric_x: r.f32(0x80)?,
+        planck_o: r.i32(0x308)? as f32,
+        planck_r2: r.f32(0x30c)?,
+    })
+}
+
+// Numbers of an FFF record in its byte order
+struct Reader<'a> {
+    data: &'a [u8],
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    // Records start with 2 in their own byte order
+    fn record(data: &'a [u8]) -> Result<Reader<'a>> {
+        let check = data.get(..2).context("FLIR record is empty")?;
+        Ok(Reader {
+            data,
+            big_endian: u16::from_le_bytes([check[0], check[1]]) >= 0x100,
+        })
+    }
+
+    fn bytes<const N: usize>(&self, offset: usize) -> Result<[u8; N]> {
+        let mut bytes: [u8; N] = self
+            .data
+            .get(offset..offset + N)
+            .context("FLIR record is truncated")?
+            .try_into()
+            .unwrap();
+        if !self.big_endian {
+            bytes.reverse();
+        }
+        Ok(bytes)
+    }
+
+    fn u16(&self, offset: usize) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(offset)?))
+    }
+
+    fn u32(&self, offset: usize) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(offset)?))
+    }
+
+    fn i32(&self, offset: usize) -> Result<i32> {
+        Ok(i32::from_be_bytes(self.bytes(offset)?))
+    }
+
+    fn f32(&self, offset: usize) -> Result<f32> {
+        Ok(f32::from_be_bytes(self.bytes(offset)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use image::{ImageBuffer, Luma};
+
+    use super::*;
+
+    const RAW: [u16; 6] = [17000, 17100, 17200, 17300, 17400, 0xfffe];
+
+    fn put(data: &mut Vec<u8>, offset: usize, be_bytes: &[u8], big_endian: bool) {
+        if data.len() < offset + be_bytes.len() {
+            data.resize(offset + be_bytes.len(), 0);
+        }
+        let target = &mut data[offset..offset + be_bytes.len()];
+        target.copy_from_slice(be_bytes);
+        if !big_endian {
+            target.reverse();
+        }
+    }
+
+    fn camera() -> CameraInfo {
+        let [alpha1, alpha2, beta1, beta2, x] = ATMOSPHERE;
+        CameraInfo {
+            emissivity: 0.95,
+            object_distance: 2.0,
+            reflected_temperature: 20.0,
+            atmospheric_temperature: 22.0,
+            ir_window_temperature: 20.0,
+            ir_window_transmission: 1.0,
+            relative_humidity: 0.5,
+            planck_r1: 21106.77,
+            planck_r2: 0.012545258,
+            planck_b: 1501.0,
+            planck_f: 1.0,
+            planck_o: -7340.0,
+            atmospheric_alpha1: alpha1,
+            atmospheric_alpha2: alpha2,
+            atmospheric_beta1: beta1,
+            atmospheric_beta2: beta2,
+            atmospheric_x: x,
+        }
+    }
+
+    fn raw_record(payload: &str, big_endian: bool) -> Vec<u8> {
+        let mut record = Vec::new();
+        put(&mut record, 0, &2u16.to_be_bytes(), big_endian);
+        put(&mut record, 0x02, &3u16.to_be_bytes(), big_endian);
+        put(&mut record, 0x04, &2u16.to_be_bytes(), big_endian);
+        record.resize(0x20, 0);
+
+        let frame = |values: Vec<u16>| ImageBuffer::<Luma<u16>, _>::from_raw(3, 2, values).unwrap();
+        let mut encoded = Cursor::new(Vec::new());
+        match payload {
+            "png" => frame(RAW.map(u16::swap_bytes).to_vec())
+                .write_to(&mut encoded, ImageFormat::Png)
+                .unwrap(),
+            "tiff" => frame(RAW.to_vec())
+                .write_to(&mut encoded, ImageFormat::Tiff)
+                .unwrap(),
+            _ => {
+                for (i, v) in RAW.iter().enumerate() {
+                    put(&mut record, 0x20 + i * 2, &v.to_be_bytes(), big_endian);
+                }
+            }
+        }
+        record.extend(encoded.into_inner());
+        record
+    }
+
+    fn camera_record(c: &CameraInfo, big_endian: bool) -> Vec<u8> {
+        let mut record = Vec::new();
+        put(&mut record, 0, &2u16.to_be_bytes(), big_endian);
+        let floats = [
+            (0x20, c.emissivity),
+            (0x24, c.object_distance),
+            (0x28, c.reflected_temperature + KELVIN),
+            (0x2c, c.atmospheric_temperature + KELVIN),
+            (0x30, c.ir_window_temperature + KELVIN),
+            (0x34, c.ir_window_transmission),
+            // As a percentage
+            (0x3c, c.relative_humidity * 100.0),
+            (0x58, c.planck_r1),
+            (0x5c, c.planck_b),
+            (0x60, c.planck_f),
+            (0x70, c.atmospheric_alpha1),
+            (0x74, c.atmospheric_alpha2),
+            (0x78, c.atmospheric_beta1),
+            (0x7c, c.atmospheric_beta2),
+            (0x80, c.atmospheric_x),
+            (0x30c, c.planck_r2),
+        ];
+        for (offset, v) in floats {
+            put(&mut record, offset, &v.to_be_bytes(), big_endian);
+        }
+        put(
+            &mut record,
+            0x308,
+            &(c.planck_o as i32).to_be_bytes(),
+            big_endian,
+        );
+        record
+    }
+
+    fn fff(records: &[(u16, Vec<u8>)], big_endian: bool) -> Vec<u8> {
+        let mut fff = b"FFF\0".to_vec();
+        put(&mut fff, 0x14, &101u32.to_be_bytes(), big_endian);
+        put(&mut fff, 0x18, &0x40u32.to_be_bytes(), big_endian);
+        put(
+            &mut fff,
+            0x1c,
+            &(records.len() as u32).to_be_bytes(),
+            big_endian,
+        );
+        fff.resize(0x40 + records.len() * 0x20, 0);
+        for (i, (kind, record)) in records.iter().enumerate() {
+            let entry = 0x40 + i * 0x20;
+            let offset = fff.len() as u32;
+            put(&mut fff, entry, &kind.to_be_bytes(), big_endian);
+            put(&mut fff, entry + 0x0c, &offset.to_be_bytes(), big_endian);
+            put(
+                &mut fff,
+                entry + 0x10,
+                &(record.len() as u32).to_be_bytes(),
+                big_endian,
+            );
+            fff.extend(record);
+        }
+        fff
+    }
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() <= b.abs() * 1e-5 + 1e-4, "{a} is not {b}");
+    }
+
+    #[test]
+    fn parse_in_both_byte_orders() {
+        let c = camera();
+        for big_endian in [false, true] {
+            for payload in ["raw", "png", "tiff"] {
+                let fff = fff(
+                    &[
+                        (0x05, vec![0; 8]),
+                        (CAMERA_INFO, camera_record(&c, big_endian)),
+                        (RAW_DATA, raw_record(payload, big_endian)),
+                    ],
+                    big_endian,
+                );
+                let r = parse(&fff).unwrap();
+                assert_eq!((r.width, r.height), (3, 2), "{payload}");
+                assert_eq!(r.raw, RAW, "{payload}, big endian {big_endian}");
+
+                let Camera::Flir(parsed) = r.camera else {
+                    panic!("not a FLIR camera");
+                };
+                assert_close(parsed.emissivity, c.emissivity);
+                assert_close(parsed.reflected_temperature, c.reflected_temperature);
+                assert_close(parsed.atmospheric_temperature, c.atmospheric_temperature);
+                assert_close(parsed.relative_humidity, c.relative_humidity);
+                assert_close(parsed.planck_r1, c.planck_r1);
+                assert_close(parsed.planck_r2, c.planck_r2);
+                assert_close(parsed.planck_o, c.planck_o);
+                assert_close(parsed.atmospheric_x, c.atmospheric_x);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_rejects_unusable_data() {
+        let c = camera();
+        assert!(parse(b"").is_err());
+        assert!(parse(b"FFF\0").is_err());
+        assert!(parse(&fff(&[], false)).is_err());
+        assert!(parse(&fff(&[(RAW_DATA, raw_record("raw", false))], false)).is_err());
+        assert!(parse(&fff(&[(CAMERA_INFO, camera_record(&c, false))], false)).is_err());
+
+        let mut short = raw_record("raw", true);
+        short.truncate(0x24);
+        assert!(
+            parse(&fff(
+                &[(RAW_DATA, short), (CAMERA_INFO, camera_record(&c, true))],
+                true
+            ))
+            .is_err()
+        );
+
+        let mut fff = fff(&[(RAW_DATA, raw_record("raw", false))], false);
+        fff.truncate(0x70);
+        assert!(parse(&fff).is_err());
+    }
+
+    #[test]
+    fn read_joins_segments_in_order() {
+        let fff = fff(
+            &[
+                (RAW_DATA, raw_record("raw", false)),
+                (CAMERA_INFO, camera_record(&camera(), false)),
+            ],
+            false,
+        );
+        let (first, second) = fff.split_at(fff.len() / 2);
+
+        let mut bytes = vec![0xff, 0xd8];
+        for (index, chunk) in [(1u8, second), (0, first)] {
+            let mut payload = b"FLIR\0\x01".to_vec();
+            payload.extend([index, 1]);
+            payload.extend_from_slice(chunk);
+            bytes.extend([0xff, APP1]);
+            bytes.extend((payload.len() as u16 + 2).to_be_bytes());
+            bytes.extend(payload);
+        }
+        bytes.extend([0xff, 0xd9]);
+
+        assert_eq!(read(&bytes).unwrap().unwrap().raw, RAW);
+        assert!(read(&[0xff, 0xd8, 0xff, 0xd9]).unwrap().is_none());
+    }
+
+    #[test]
+    fn converter_inverts_raw_of() {
+        let c = camera();
+        for t in [-10.0f32, 0.0, 25.0, 36.6, 100.0] {
+            assert!((c.temperature_of(c.raw_of(t)) - t).abs() < 0.01);
+        }
+
+        // A black body seen through nothing reads its own temperature
+        let ideal = CameraInfo {
+            emissivity: 1.0,
+            object_distance: 0.0,
+            ir_window_transmission: 1.0,
+            ..c
+        };
+        let convert = ideal.converter();
+        for t in [-10.0f32, 0.0, 25.0, 36.6, 100.0] {
+            let raw = ideal.raw_of(t).round() as u16;
+            assert!(
+                (convert(raw) - t).abs() < 0.1,
+                "{} is not {t}",
+                convert(raw)
+            );
+        }
+
+        // Reflections of a colder room make a less emissive object read warmer
+        let raw = c.raw_of(40.0).round() as u16;
+        assert!(c.converter()(raw) > convert(raw));
+    }
+}
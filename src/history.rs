@@ -0,0 +1,126 @@
+// Oldest entries are dropped beyond this
+const LIMIT: usize = 100;
+
+/// A named edit, with the state before and after it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Change<S> {
+    pub name: String,
+    pub before: S,
+    pub after: S,
+}
+
+#[derive(Debug, Clone)]
+pub struct History<S> {
+    pub done: Vec<Change<S>>,
+    pub undone: Vec<Change<S>>,
+}
+
+impl<S> Default for History<S> {
+    fn default() -> Self {
+        History {
+            done: Vec::new(),
+            undone: Vec::new(),
+        }
+    }
+}
+
+impl<S> History<S> {
+    // A new edit makes the undone ones unreachable
+    pub fn push(&mut self, change: Change<S>) {
+        self.undone.clear();
+        self.done.push(change);
+        if self.done.len() > LIMIT {
+            self.done.remove(0);
+        }
+    }
+
+    // Adds `change` to the last entry when it carries on from it: the same
+    // kind of edit, starting where that one ended, with nothing undone since
+    pub fn extend(&mut self, change: Change<S>)
+    where
+        S: PartialEq,
+    {
+        match self.done.last_mut() {
+            Some(last)
+                if last.name == change.name
+                    && last.after == change.before
+                    && self.undone.is_empty() =>
+            {
+                last.after = change.after;
+            }
+            _ => self.push(change),
+        }
+    }
+
+    pub fn undo(&mut self) -> Option<&Change<S>> {
+        let change = self.done.pop()?;
+        self.undone.push(change);
+        self.undone.last()
+    }
+
+    pub fn redo(&mut self) -> Option<&Change<S>> {
+        let change = self.undone.pop()?;
+        self.done.push(change);
+        self.done.last()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(name: &str, before: u32, after: u32) -> Change<u32> {
+        Change {
+            name: name.to_string(),
+            before,
+            after,
+        }
+    }
+
+    #[test]
+    fn undo_and_redo_move_entries_between_the_stacks() {
+        let mut history = History::default();
+        history.push(change("a", 0, 1));
+        history.push(change("b", 1, 2));
+        assert_eq!(history.undo(), Some(&change("b", 1, 2)));
+        assert_eq!(history.undo(), Some(&change("a", 0, 1)));
+        assert_eq!(history.undo(), None);
+        assert_eq!(history.redo(), Some(&change("a", 0, 1)));
+
+        // A new edit drops what was undone
+        history.push(change("c", 1, 3));
+        assert_eq!(history.redo(), None);
+        assert_eq!(history.done, [change("a", 0, 1), change("c", 1, 3)]);
+    }
+
+    #[test]
+    fn push_keeps_the_latest_entries() {
+        let mut history = History::default();
+        for i in 0..LIMIT as u32 + 5 {
+            history.push(change("a", i, i + 1));
+        }
+        assert_eq!(history.done.len(), LIMIT);
+        assert_eq!(history.done[0], change("a", 5, 6));
+    }
+
+    #[test]
+    fn extend_merges_only_edits_that_carry_on() {
+        let mut history = History::default();
+        history.extend(change("Rename", 0, 1));
+        history.extend(change("Rename", 1, 2));
+        assert_eq!(history.done, [change("Rename", 0, 2)]);
+
+        // Another kind of edit is a new entry, and so is one after an undo
+        history.extend(change("Move", 2, 3));
+        history.undo();
+        history.extend(change("Rename", 2, 3));
+        assert_eq!(
+            history.done,
+            [change("Rename", 0, 2), change("Rename", 2, 3)]
+        );
+
+        // Or one that starts elsewhere
+        history.extend(change("Rename", 5, 6));
+        assert_eq!(history.done.len(), 3);
+    }
+}
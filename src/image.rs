@@ -1,95 +1,657 @@
-use anyhow::{bail, Result};
+use anyhow::{Context, Result};
 use image::RgbaImage;
-use macroquad::prelude::*;
-use std::{
-    path::PathBuf,
-    sync::{Arc, Mutex},
-};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::path::{Path, PathBuf};
 
-use crate::map::Map;
+use crate::{
+    calibration::{Bar, Calibration},
+    field::TemperatureField,
+    filter::Filter,
+    flir::CameraInfo,
+    isotherm::{Isotherm, render_overlay},
+    map::{ColorIndex, Lookup, Map},
+    ocr::{Reading, Recognizer},
+    palette::{Palette, Recolor},
+    project::ImageProject,
+    radiometric::{self, Camera, Radiometric},
+    render::{Layer, Operation, RenderKey, blend, default_layers, draw_annotations},
+    roi::Roi,
+    spot::Spot,
+};
 
 #[derive(Debug, Clone)]
 pub struct ImageData {
     pub raw_image: RgbaImage,
-    pub image: RgbaImage,
     pub min: f32,
     pub max: f32,
     pub step: f32,
+    pub filter: Filter,
+    pub color_temp: Map<[u8; 3], f32>,
+    pub color_index: ColorIndex<f32>,
+    pub lookup: Lookup,
+    pub bar: Option<Bar>,
+    pub detected_bar: Option<Bar>,
+    pub min_reading: Option<Reading>,
+    pub max_reading: Option<Reading>,
+    pub temperatures: TemperatureField,
+    // Sensor data the temperatures come from instead of the colors, if any
+    pub radiometric: Option<Radiometric>,
+    pub palette: Option<Palette>,
+    pub recolor: Recolor,
+    pub rois: Vec<Roi>,
+    pub spots: Vec<Spot>,
+    pub isotherms: Vec<Isotherm>,
+    // Bumped whenever the temperatures change
+    pub revision: u32,
+}
+
+/// How the viewer shows an image, which its temperatures do not depend on
+#[derive(Debug, Clone, PartialEq)]
+pub struct View {
+    pub layers: Vec<Operation>,
+    // Folder-wide actions skip the image
+    pub override_calibration: bool,
+    pub notes: String,
+}
+
+/// Color map and temperatures worked out from a snapshot of an image
+#[derive(Debug, Clone)]
+pub struct Extraction {
+    pub color_temp: Option<Map<[u8; 3], f32>>,
+    pub temperatures: TemperatureField,
+}
+
+// Everything the user can edit on an image, as kept in the undo history
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Settings {
+    pub min: f32,
+    pub max: f32,
+    pub step: f32,
+    pub lookup: Lookup,
+    pub bar: Option<Bar>,
+    pub palette: Option<Palette>,
+    pub color_temp: Map<[u8; 3], f32>,
+    pub override_calibration: bool,
     pub filter_min_enabled: bool,
     pub filter_min: f32,
     pub filter_max_enabled: bool,
     pub filter_max: f32,
-    pub color_temp: Map<[u8; 3], f32>,
+    pub recolor: Recolor,
+    pub rois: Vec<Roi>,
+    pub spots: Vec<Spot>,
+    pub isotherms: Vec<Isotherm>,
+    pub layers: Vec<Operation>,
+    #[serde(default, deserialize_with = "stored_camera")]
+    pub camera: Option<Camera>,
 }
 
-#[derive(Debug, Clone)]
-pub struct Image {
-    pub path: PathBuf,
-    pub is_loading: Arc<Mutex<bool>>,
-    pub texture: Option<Texture2D>,
-    pub data: Arc<Mutex<Option<ImageData>>>,
+// Projects of version 1 stored FLIR parameters, the only camera there was,
+// without naming the camera
+fn stored_camera<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Camera>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Stored {
+        Camera(Camera),
+        Flir(CameraInfo),
+    }
+
+    Ok(
+        Option::<Stored>::deserialize(deserializer)?.map(|stored| match stored {
+            Stored::Camera(camera) => camera,
+            Stored::Flir(info) => Camera::Flir(info),
+        }),
+    )
 }
 
-impl Image {
-    pub fn new(path: PathBuf) -> Result<Image> {
-        if !path.exists() {
-            bail!("Image path does not exist: {}", path.display());
+impl Settings {
+    // Short name for what differs from `self` to `after`, like "Add spot"
+    pub fn describe_change(&self, after: &Settings) -> String {
+        let counted = |what: &str, before: usize, after: usize| match before.cmp(&after) {
+            std::cmp::Ordering::Less => format!("Add {what}"),
+            std::cmp::Ordering::Greater => format!("Delete {what}"),
+            std::cmp::Ordering::Equal => format!("Edit {what}"),
+        };
+
+        let mut parts = Vec::new();
+        if self.min != after.min
+            || self.max != after.max
+            || self.step != after.step
+            || self.lookup != after.lookup
+            || self.bar != after.bar
+            || self.palette != after.palette
+            || self.color_temp != after.color_temp
+            || self.override_calibration != after.override_calibration
+        {
+            parts.push("Calibration".to_string());
+        }
+        if self.camera != after.camera {
+            parts.push("Camera".to_string());
+        }
+        if self.filter_min_enabled != after.filter_min_enabled
+            || self.filter_min != after.filter_min
+            || self.filter_max_enabled != after.filter_max_enabled
+            || self.filter_max != after.filter_max
+        {
+            parts.push("Filter".to_string());
+        }
+        if self.recolor != after.recolor {
+            parts.push("Recolor".to_string());
+        }
+        if self.rois != after.rois {
+            let renamed = renamed(&self.rois, &after.rois, |r| &r.name, |r| &r.shape);
+            parts.push(if renamed {
+                "Rename region".to_string()
+            } else {
+                counted("region", self.rois.len(), after.rois.len())
+            });
         }
+        if self.spots != after.spots {
+            let renamed = renamed(&self.spots, &after.spots, |s| &s.name, |s| &s.position);
+            parts.push(if renamed {
+                "Rename spot".to_string()
+            } else {
+                counted("spot", self.spots.len(), after.spots.len())
+            });
+        }
+        if self.isotherms != after.isotherms {
+            parts.push(counted(
+                "isotherm",
+                self.isotherms.len(),
+                after.isotherms.len(),
+            ));
+        }
+        if self.layers != after.layers {
+            parts.push("Layers".to_string());
+        }
+        parts.join(", ")
+    }
+}
 
-        Ok(Image {
-            path,
-            is_loading: Arc::new(Mutex::new(false)),
-            texture: None,
-            data: Arc::new(Mutex::new(None)),
-        })
+// Whether only names differ between `before` and `after`
+fn renamed<T, P: PartialEq>(
+    before: &[T],
+    after: &[T],
+    name: impl Fn(&T) -> &String,
+    rest: impl Fn(&T) -> &P,
+) -> bool {
+    before.len() == after.len()
+        && before.iter().zip(after).all(|(b, a)| rest(b) == rest(a))
+        && before.iter().zip(after).any(|(b, a)| name(b) != name(a))
+}
+
+impl ImageData {
+    // Decodes the image and derives temperatures from its embedded sensor data,
+    // or else from its scale bar and labels
+    pub fn open(
+        path: &Path,
+        recognizer: &Recognizer,
+        folder_bar: Option<Bar>,
+    ) -> Result<ImageData> {
+        let bytes =
+            std::fs::read(path).context(format!("Failed to read image {}", path.display()))?;
+        let decoded = image::load_from_memory(&bytes)
+            .context(format!("Failed to open image {}", path.display()))?;
+        // 16-bit grayscale frames are counts, shown through a palette
+        if let Some(frame) = decoded.as_luma16() {
+            let radiometric = Radiometric::from_counts(frame);
+            return Ok(ImageData::from_radiometric(
+                radiometric.preview(),
+                radiometric,
+            ));
+        }
+
+        let image = decoded.into_rgba8();
+        if let Some(radiometric) = radiometric::read(&bytes, image.width(), image.height()) {
+            return Ok(ImageData::from_radiometric(image, radiometric));
+        }
+
+        let detected = Calibration::detect(&image, recognizer);
+        let detected_bar = detected.as_ref().map(|c| c.bar);
+        let Calibration {
+            min,
+            max,
+            step,
+            min_reading,
+            max_reading,
+            ..
+        } = detected.unwrap_or_default();
+
+        let mut d = ImageData {
+            raw_image: image,
+            min,
+            max,
+            step,
+            color_temp: Map::new(),
+            color_index: ColorIndex::default(),
+            lookup: Lookup::default(),
+            bar: None,
+            detected_bar,
+            min_reading,
+            max_reading,
+            filter: Filter::default(),
+            temperatures: TemperatureField::default(),
+            radiometric: None,
+            palette: None,
+            recolor: Recolor {
+                palette: Palette::builtin().remove(0),
+                min,
+                max,
+            },
+            rois: Vec::new(),
+            spots: Vec::new(),
+            isotherms: Vec::new(),
+            revision: 0,
+        };
+        if detected_bar.is_some() || folder_bar.is_some() {
+            d.extract(folder_bar);
+        }
+        Ok(d)
+    }
+
+    // Takes the range from the measured temperatures, there is no bar to read
+    fn from_radiometric(image: RgbaImage, radiometric: Radiometric) -> ImageData {
+        let temperatures = radiometric.temperatures(image.width(), image.height());
+        let (min, max) = (10.0, 30.0);
+        let mut d = ImageData {
+            raw_image: image,
+            min,
+            max,
+            step: 0.1,
+            color_temp: Map::new(),
+            color_index: ColorIndex::default(),
+            lookup: Lookup::default(),
+            bar: None,
+            detected_bar: None,
+            min_reading: None,
+            max_reading: None,
+            filter: Filter::default(),
+            temperatures,
+            radiometric: Some(radiometric),
+            palette: None,
+            recolor: Recolor {
+                palette: Palette::builtin().remove(0),
+                min,
+                max,
+            },
+            rois: Vec::new(),
+            spots: Vec::new(),
+            isotherms: Vec::new(),
+            revision: 0,
+        };
+        d.fit_range();
+        d
     }
 
-    pub fn load(&mut self) -> Result<()> {
-        let mut is_loading = self.is_loading.lock().unwrap();
-        if *is_loading {
-            return Ok(());
+    // Spans the range, the filter and the recolor palette over the temperatures
+    pub fn fit_range(&mut self) {
+        let Some(stats) = self.temperatures.stats() else {
+            return;
+        };
+        self.min = stats.min;
+        self.max = stats.max;
+        if let Some(min) = &mut self.filter.min {
+            *min = stats.min;
+        }
+        if let Some(max) = &mut self.filter.max {
+            *max = stats.max;
         }
-        *is_loading = true;
+        self.recolor.min = stats.min;
+        self.recolor.max = stats.max;
+    }
 
-        let path = self.path.clone();
-        let data = Arc::clone(&self.data);
-        let is_loading = Arc::clone(&self.is_loading);
+    pub fn set_color_temp(&mut self, color_temp: Map<[u8; 3], f32>) {
+        self.color_index = color_temp.index();
+        self.color_temp = color_temp;
+    }
 
-        std::thread::spawn(move || {
-            let mut data = data.lock().unwrap();
-            if data.is_some() {
-                eprintln!("Image data is already loaded for: {}", path.display());
-                return;
-            }
+    pub fn temperature_of(&self, rgb: [u8; 3]) -> Option<(f32, f32)> {
+        self.color_index.lookup(rgb, self.lookup)
+    }
 
-            let image = match image::open(&path) {
-                Ok(img) => img.into_rgba8(),
-                Err(e) => {
-                    eprintln!("Failed to open image {}: {e}", path.display());
-                    return;
-                }
+    pub fn temperature_at(&self, x: u32, y: u32) -> Option<f32> {
+        self.temperatures.get(x, y)
+    }
+
+    // Uses the chosen palette, or the scale bar when there is none
+    pub fn calibration(&self, folder_bar: Option<Bar>) -> Calibration {
+        Calibration {
+            bar: self.effective_bar(folder_bar),
+            palette: self.palette.clone(),
+            min: self.min,
+            max: self.max,
+            step: self.step,
+            lookup: self.lookup,
+            min_reading: self.min_reading,
+            max_reading: self.max_reading,
+        }
+    }
+
+    pub fn extract(&mut self, folder_bar: Option<Bar>) {
+        self.apply_extraction(self.extraction(folder_bar, true));
+    }
+
+    // The slow part of `extract`, which only reads the image so it can run on a
+    // snapshot. Without `color_map` the current color map is kept. Skips the bar
+    // and its labels, which are part of the palette but not of the scene, and
+    // sensor data wins over any colors.
+    pub fn extraction(&self, folder_bar: Option<Bar>, color_map: bool) -> Extraction {
+        let calibration = self.calibration(folder_bar);
+        if let Some(r) = &self.radiometric {
+            return Extraction {
+                color_temp: None,
+                temperatures: r.temperatures(self.raw_image.width(), self.raw_image.height()),
             };
+        }
 
-            let min = 10.0;
-            let max = 30.0;
-            let step = 0.1;
-            let color_temp = Map::new();
+        let color_temp = color_map.then(|| calibration.color_map(&self.raw_image));
+        let index = color_temp.as_ref().map(Map::index);
+        let temperatures = TemperatureField::from_image(
+            &self.raw_image,
+            index.as_ref().unwrap_or(&self.color_index),
+            self.lookup,
+            &calibration.excluded(),
+        );
+        Extraction {
+            color_temp,
+            temperatures,
+        }
+    }
 
-            *data = Some(ImageData {
-                raw_image: image.clone(),
-                image,
-                min,
-                max,
-                step,
-                color_temp,
-                filter_min_enabled: false,
-                filter_min: min,
-                filter_max_enabled: false,
-                filter_max: max,
-            });
-            *is_loading.lock().unwrap() = false;
-        });
+    pub fn apply_extraction(&mut self, extraction: Extraction) {
+        if let Some(color_temp) = extraction.color_temp {
+            self.set_color_temp(color_temp);
+        }
+        self.temperatures = extraction.temperatures;
+        self.revision = self.revision.wrapping_add(1);
+    }
+
+    // Takes over the palette and range of another image, keeping everything else
+    pub fn apply_calibration(&mut self, from: &ImageData, folder_bar: Option<Bar>) {
+        self.take_calibration(from);
+        self.apply_extraction(self.extraction(folder_bar, false));
+    }
+
+    // The quick part of `apply_calibration`, leaving the temperatures as they are
+    pub fn take_calibration(&mut self, from: &ImageData) {
+        self.min = from.min;
+        self.max = from.max;
+        self.step = from.step;
+        self.palette = from.palette.clone();
+        self.lookup = from.lookup;
+        self.recolor = from.recolor.clone();
+        // Sensor data leaves the color map of a radiometric image empty
+        if from.radiometric.is_none() {
+            self.set_color_temp(from.color_temp.clone());
+        }
+    }
+
+    // Grays out every pixel whose temperature is outside the filter range
+    pub fn filtered(&self) -> RgbaImage {
+        self.filter.apply(&self.raw_image, &self.temperatures)
+    }
+
+    pub fn settings(&self, view: &View) -> Settings {
+        Settings {
+            min: self.min,
+            max: self.max,
+            step: self.step,
+            lookup: self.lookup,
+            bar: self.bar,
+            palette: self.palette.clone(),
+            color_temp: self.color_temp.clone(),
+            override_calibration: view.override_calibration,
+            // A disabled bound shows the end of the range
+            filter_min_enabled: self.filter.min.is_some(),
+            filter_min: self.filter.min.unwrap_or(self.min),
+            filter_max_enabled: self.filter.max.is_some(),
+            filter_max: self.filter.max.unwrap_or(self.max),
+            recolor: self.recolor.clone(),
+            rois: self.rois.clone(),
+            spots: self.spots.clone(),
+            isotherms: self.isotherms.clone(),
+            layers: view.layers.clone(),
+            camera: self.radiometric.as_ref().map(|r| r.camera),
+        }
+    }
+
+    // Goes back to earlier settings, deriving temperatures again only when
+    // the color map or the excluded regions differ. What the view keeps is
+    // left to `View::restore`.
+    pub fn restore(&mut self, s: &Settings, folder_bar: Option<Bar>) {
+        let recalibrate = s.color_temp != self.color_temp
+            || s.lookup != self.lookup
+            || s.bar != self.bar
+            || s.palette != self.palette
+            || s.camera.is_some() && s.camera != self.radiometric.as_ref().map(|r| r.camera);
+
+        self.min = s.min;
+        self.max = s.max;
+        self.step = s.step;
+        self.lookup = s.lookup;
+        self.bar = s.bar;
+        self.palette = s.palette.clone();
+        self.filter = Filter {
+            min: s.filter_min_enabled.then_some(s.filter_min),
+            max: s.filter_max_enabled.then_some(s.filter_max),
+        };
+        self.recolor = s.recolor.clone();
+        self.rois = s.rois.clone();
+        self.spots = s.spots.clone();
+        self.isotherms = s.isotherms.clone();
+        if let (Some(r), Some(camera)) = (&mut self.radiometric, s.camera) {
+            r.camera = camera;
+        }
+        if recalibrate {
+            self.set_color_temp(s.color_temp.clone());
+            self.apply_extraction(self.extraction(folder_bar, false));
+        }
+    }
+
+    pub fn project(&self, view: &View) -> ImageProject {
+        ImageProject {
+            settings: self.settings(view),
+            notes: view.notes.clone(),
+        }
+    }
+
+    pub fn apply_project(&mut self, project: &ImageProject, folder_bar: Option<Bar>) {
+        self.restore(&project.settings, folder_bar);
+    }
+
+    // Runs the enabled layers over the raw image, which is left untouched
+    pub fn render(&self, layers: &[Operation]) -> RgbaImage {
+        let mut image = self.raw_image.clone();
+        for op in layers.iter().filter(|op| op.enabled) {
+            match op.layer {
+                Layer::Palette => {
+                    let r = &self.recolor;
+                    image = r
+                        .palette
+                        .render(&image, &self.temperatures.values, r.min, r.max);
+                }
+                Layer::Filter => image = self.filter.apply(&image, &self.temperatures),
+                Layer::Isotherms => {
+                    if self.isotherms.iter().any(|b| b.enabled) {
+                        blend(
+                            &mut image,
+                            &render_overlay(&self.isotherms, &self.temperatures),
+                        );
+                    }
+                }
+                Layer::Annotations => draw_annotations(&mut image, &self.rois, &self.spots),
+            }
+        }
+        image
+    }
+
+    pub fn render_key(&self, layers: &[Operation]) -> RenderKey {
+        let annotations = layers
+            .iter()
+            .any(|op| op.enabled && op.layer == Layer::Annotations);
+        RenderKey {
+            layers: layers.to_vec(),
+            recolor: self.recolor.clone(),
+            filter: self.filter,
+            isotherms: self.isotherms.clone(),
+            rois: if annotations {
+                self.rois.clone()
+            } else {
+                Vec::new()
+            },
+            spots: if annotations {
+                self.spots.clone()
+            } else {
+                Vec::new()
+            },
+            revision: self.revision,
+        }
+    }
+
+    // A bar picked by hand wins over the folder bar, which wins over detection
+    pub fn effective_bar(&self, folder_bar: Option<Bar>) -> Bar {
+        self.bar
+            .or(folder_bar)
+            .or(self.detected_bar)
+            .unwrap_or_default()
+    }
+}
+
+impl Default for View {
+    fn default() -> View {
+        View {
+            layers: default_layers(),
+            override_calibration: false,
+            notes: String::new(),
+        }
+    }
+}
+
+impl View {
+    // 16-bit frames are counts and show through a palette
+    pub fn new(d: &ImageData) -> View {
+        let mut view = View::default();
+        if let Some(Radiometric {
+            camera: Camera::Counts(_),
+            ..
+        }) = &d.radiometric
+        {
+            view.set_layer(Layer::Palette, true);
+        }
+        view
+    }
+
+    pub fn layer_enabled(&self, layer: Layer) -> bool {
+        self.layers.iter().any(|op| op.layer == layer && op.enabled)
+    }
+
+    pub fn set_layer(&mut self, layer: Layer, enabled: bool) {
+        for op in self.layers.iter_mut().filter(|op| op.layer == layer) {
+            op.enabled = enabled;
+        }
+    }
+
+    // The part of `ImageData::restore` the view keeps
+    pub fn restore(&mut self, s: &Settings) {
+        self.layers = s.layers.clone();
+        self.override_calibration = s.override_calibration;
+    }
+
+    pub fn apply_project(&mut self, project: &ImageProject) {
+        self.restore(&project.settings);
+        self.notes = project.notes.clone();
+    }
+}
+
+pub const EXTENSIONS: [&str; 6] = ["png", "jpg", "jpeg", "bmp", "tif", "tiff"];
+
+pub fn list_images(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .context(format!("Failed to read directory {}", dir.display()))?
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|ext| EXTENSIONS.iter().any(|e| ext.eq_ignore_ascii_case(e)))
+        })
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(camera: Option<Camera>) -> Settings {
+        let recolor = Recolor {
+            palette: Palette::builtin().remove(0),
+            min: 10.0,
+            max: 30.0,
+        };
+        Settings {
+            min: 10.0,
+            max: 30.0,
+            step: 0.1,
+            lookup: Lookup::default(),
+            bar: None,
+            palette: None,
+            color_temp: Map::new(),
+            override_calibration: false,
+            filter_min_enabled: true,
+            filter_min: 20.0,
+            filter_max_enabled: false,
+            filter_max: 30.0,
+            recolor,
+            rois: Vec::new(),
+            spots: Vec::new(),
+            isotherms: Vec::new(),
+            layers: default_layers(),
+            camera,
+        }
+    }
+
+    #[test]
+    fn settings_read_cameras_of_every_version() {
+        let info = CameraInfo {
+            emissivity: 0.95,
+            object_distance: 1.0,
+            reflected_temperature: 20.0,
+            atmospheric_temperature: 20.0,
+            ir_window_temperature: 20.0,
+            ir_window_transmission: 1.0,
+            relative_humidity: 0.5,
+            planck_r1: 21106.77,
+            planck_r2: 0.012545258,
+            planck_b: 1501.0,
+            planck_f: 1.0,
+            planck_o: -7340.0,
+            atmospheric_alpha1: 0.006569,
+            atmospheric_alpha2: 0.01262,
+            atmospheric_beta1: -0.002276,
+            atmospheric_beta2: -0.00667,
+            atmospheric_x: 1.9,
+        };
+        let s = settings(Some(Camera::Flir(info)));
+        let json = serde_json::to_value(&s).unwrap();
+        assert_eq!(serde_json::from_value::<Settings>(json.clone()).unwrap(), s);
+
+        // Version 1 wrote the FLIR parameters as they are
+        let mut v1 = json.clone();
+        v1["camera"] = serde_json::to_value(info).unwrap();
+        assert_eq!(serde_json::from_value::<Settings>(v1).unwrap(), s);
 
-        Ok(())
+        let mut none = json;
+        none["camera"] = serde_json::Value::Null;
+        assert_eq!(
+            serde_json::from_value::<Settings>(none.clone())
+                .unwrap()
+                .camera,
+            None
+        );
+        none.as_object_mut().unwrap().remove("camera");
+        assert_eq!(
+            serde_json::from_value::<Settings>(none).unwrap().camera,
+            None
+        );
     }
 }
@@ -0,0 +1,67 @@
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::field::TemperatureField;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Fill {
+    Solid,
+    Striped,
+}
+
+/// Band of temperatures highlighted in one color
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Isotherm {
+    pub enabled: bool,
+    pub min: f32,
+    pub max: f32,
+    pub color: [u8; 4],
+    pub fill: Fill,
+}
+
+// Colors given to new bands in turn
+pub const COLORS: [[u8; 4]; 4] = [
+    [0, 255, 0, 200],
+    [0, 200, 255, 200],
+    [255, 0, 255, 200],
+    [255, 255, 0, 200],
+];
+
+impl Isotherm {
+    pub fn contains(&self, temp: f32) -> bool {
+        temp >= self.min && temp <= self.max
+    }
+
+    // Whether the band paints pixel (x, y) when it is inside it. Stripes
+    // run diagonally and are three pixels wide.
+    pub fn covers(&self, x: u32, y: u32) -> bool {
+        match self.fill {
+            Fill::Solid => true,
+            Fill::Striped => ((x + y) / 3).is_multiple_of(2),
+        }
+    }
+}
+
+/// Transparent image with every enabled band painted over the pixels inside
+/// it, later bands on top of earlier ones
+pub fn render_overlay(bands: &[Isotherm], field: &TemperatureField) -> RgbaImage {
+    let mut overlay = RgbaImage::new(field.width, field.height);
+    let bands: Vec<_> = bands.iter().filter(|b| b.enabled).collect();
+    if bands.is_empty() {
+        return overlay;
+    }
+
+    for (x, y, pixel) in overlay.enumerate_pixels_mut() {
+        let Some(temp) = field.get(x, y) else {
+            continue;
+        };
+        if let Some(band) = bands
+            .iter()
+            .rev()
+            .find(|b| b.contains(temp) && b.covers(x, y))
+        {
+            *pixel = Rgba(band.color);
+        }
+    }
+    overlay
+}
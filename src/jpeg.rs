@@ -0,0 +1,89 @@
+// Marker of the first application segment, APPn being APP0 + n
+pub const APP0: u8 = 0xe0;
+
+// Marker and payload of every segment before the compressed image data.
+// Anything that is not a JPEG gives no segments.
+pub fn segments(bytes: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut segments = Vec::new();
+    if !bytes.starts_with(&[0xff, 0xd8]) {
+        return segments;
+    }
+
+    let mut pos = 2;
+    while pos + 4 <= bytes.len() {
+        if bytes[pos] != 0xff {
+            break;
+        }
+        let marker = bytes[pos + 1];
+        // Fill bytes before a marker
+        if marker == 0xff {
+            pos += 1;
+            continue;
+        }
+        // Start of scan or end of image, nothing but image data follows
+        if marker == 0xda || marker == 0xd9 {
+            break;
+        }
+        // Markers without a length
+        if (0xd0..=0xd7).contains(&marker) || marker == 0x01 {
+            pos += 2;
+            continue;
+        }
+
+        let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let end = pos + 2 + length;
+        if length < 2 || end > bytes.len() {
+            break;
+        }
+        segments.push((marker, &bytes[pos + 4..end]));
+        pos = end;
+    }
+    segments
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(marker: u8, payload: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![0xff, marker];
+        bytes.extend_from_slice(&(payload.len() as u16 + 2).to_be_bytes());
+        bytes.extend_from_slice(payload);
+        bytes
+    }
+
+    #[test]
+    fn segments_before_scan() {
+        let mut bytes = vec![0xff, 0xd8];
+        bytes.extend(segment(APP0 + 1, b"first"));
+        // Fill bytes and a restart marker carry no segment
+        bytes.extend([0xff, 0xff, 0xff, 0xd0]);
+        bytes.extend(segment(APP0 + 4, b""));
+        bytes.extend(segment(0xda, b"scan"));
+        bytes.extend(segment(APP0 + 3, b"after"));
+
+        assert_eq!(
+            segments(&bytes),
+            vec![(APP0 + 1, &b"first"[..]), (APP0 + 4, &b""[..])]
+        );
+    }
+
+    #[test]
+    fn segments_of_broken_files() {
+        assert!(segments(b"").is_empty());
+        assert!(segments(b"\x89PNG\r\n\x1a\n").is_empty());
+        assert!(segments(&[0xff, 0xd8, 0x12, 0x34, 0x56, 0x78]).is_empty());
+
+        // A segment running past the end stops the walk, keeping what came before
+        let mut bytes = vec![0xff, 0xd8];
+        bytes.extend(segment(APP0, b"kept"));
+        let mut truncated = segment(APP0 + 1, b"truncated");
+        truncated.truncate(6);
+        bytes.extend(truncated);
+        assert_eq!(segments(&bytes), vec![(APP0, &b"kept"[..])]);
+
+        // So does a length too short to cover itself
+        let bytes = [0xff, 0xd8, 0xff, APP0, 0x00, 0x01, 0xff, 0xd9];
+        assert!(segments(&bytes).is_empty());
+    }
+}
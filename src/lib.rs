@@ -0,0 +1,45 @@
+//! Temperature extraction from false-color thermal images, or from the sensor
+//! data radiometric JPEGs carry.
+//!
+//! ```no_run
+//! use thermal_maps::{Calibration, Filter, ocr::Recognizer};
+//!
+//! let img = image::open("scan.png").unwrap().into_rgba8();
+//! let calibration = Calibration::detect(&img, &Recognizer::builtin()).unwrap_or_default();
+//! let field = calibration.apply(&img);
+//! println!("{:?}", field.stats());
+//!
+//! let filter = Filter { min: Some(25.0), max: None };
+//! filter.apply(&img, &field).save("hot.png").unwrap();
+//! ```
+
+pub mod calibration;
+pub mod dji;
+pub mod export;
+pub mod field;
+pub mod filter;
+pub mod flir;
+pub mod history;
+pub mod image;
+pub mod isotherm;
+pub mod jpeg;
+pub mod map;
+pub mod ocr;
+pub mod palette;
+pub mod project;
+pub mod radiometric;
+pub mod render;
+pub mod roi;
+pub mod spot;
+pub mod stats;
+
+pub use calibration::{Bar, Calibration};
+pub use field::TemperatureField;
+pub use filter::Filter;
+pub use image::{ImageData, Settings, View};
+pub use isotherm::Isotherm;
+pub use project::Project;
+pub use radiometric::Radiometric;
+pub use render::{Layer, Operation};
+pub use roi::{Roi, Shape};
+pub use spot::Spot;
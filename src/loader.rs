@@ -0,0 +1,264 @@
+use anyhow::{Result, bail};
+use macroquad::prelude::*;
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+use thermal_maps::{
+    Bar, ImageData, Settings, View,
+    history::{Change, History},
+    image::Extraction,
+    ocr::Recognizer,
+    project::ImageProject,
+    render::{Layer, Operation, RenderKey},
+};
+
+#[derive(Debug, Clone)]
+pub struct Image {
+    pub path: PathBuf,
+    pub is_loading: Arc<Mutex<bool>>,
+    // The file could not be opened, so it is not tried again
+    pub failed: Arc<Mutex<bool>>,
+    pub texture: Option<Texture2D>,
+    pub data: Arc<Mutex<Option<ImageData>>>,
+    // Locked after `data` whenever both are
+    pub view: Arc<Mutex<View>>,
+    // What the texture was rendered from
+    pub rendered: Option<RenderKey>,
+    pub history: History<Settings>,
+    // Settings as of the last history entry
+    pub settled: Option<Settings>,
+    // Last edits seen, which start out as the ones from the project file and
+    // are applied once the image is loaded
+    pub saved: Option<ImageProject>,
+}
+
+impl Image {
+    pub fn new(path: PathBuf) -> Result<Image> {
+        if !path.exists() {
+            bail!("Image path does not exist: {}", path.display());
+        }
+
+        Ok(Image {
+            path,
+            is_loading: Arc::new(Mutex::new(false)),
+            failed: Arc::new(Mutex::new(false)),
+            texture: None,
+            data: Arc::new(Mutex::new(None)),
+            view: Arc::new(Mutex::new(View::default())),
+            rendered: None,
+            history: History::default(),
+            settled: None,
+            saved: None,
+        })
+    }
+
+    // Records everything that changed since the last call as one entry,
+    // returning whether there was anything. Typing a name adds to the entry
+    // of the letters before it.
+    pub fn track_changes(&mut self) -> bool {
+        let data = self.data.lock().unwrap();
+        let Some(d) = data.as_ref() else {
+            return false;
+        };
+        let now = d.settings(&self.view.lock().unwrap());
+        match &self.settled {
+            Some(before) if *before == now => false,
+            Some(before) => {
+                let change = Change {
+                    name: before.describe_change(&now),
+                    before: before.clone(),
+                    after: now.clone(),
+                };
+                if change.name.starts_with("Rename") {
+                    self.history.extend(change);
+                } else {
+                    self.history.push(change);
+                }
+                self.settled = Some(now);
+                true
+            }
+            None => {
+                self.settled = Some(now);
+                false
+            }
+        }
+    }
+
+    // Current edits, or the last ones seen while the image is busy and its
+    // data is locked
+    pub fn project(&mut self) -> Option<ImageProject> {
+        if let Ok(data) = self.data.try_lock()
+            && let Some(d) = data.as_ref()
+        {
+            self.saved = Some(d.project(&self.view.lock().unwrap()));
+        }
+        self.saved.clone()
+    }
+
+    pub fn undo(&mut self, folder_bar: Option<Bar>) {
+        if *self.is_loading.lock().unwrap() {
+            return;
+        }
+        let mut data = self.data.lock().unwrap();
+        if let Some(d) = data.as_mut()
+            && let Some(change) = self.history.undo()
+        {
+            d.restore(&change.before, folder_bar);
+            self.view.lock().unwrap().restore(&change.before);
+            self.settled = Some(change.before.clone());
+        }
+    }
+
+    pub fn redo(&mut self, folder_bar: Option<Bar>) {
+        if *self.is_loading.lock().unwrap() {
+            return;
+        }
+        let mut data = self.data.lock().unwrap();
+        if let Some(d) = data.as_mut()
+            && let Some(change) = self.history.redo()
+        {
+            d.restore(&change.after, folder_bar);
+            self.view.lock().unwrap().restore(&change.after);
+            self.settled = Some(change.after.clone());
+        }
+    }
+
+    // Renders the layers again when any of them changed
+    pub fn update_texture(&mut self) {
+        let data = self.data.lock().unwrap();
+        let Some(d) = data.as_ref() else {
+            return;
+        };
+        // Annotations are drawn over the texture, where they can show names
+        let layers: Vec<Operation> = self
+            .view
+            .lock()
+            .unwrap()
+            .layers
+            .iter()
+            .map(|op| Operation {
+                enabled: op.enabled && op.layer != Layer::Annotations,
+                ..*op
+            })
+            .collect();
+        let key = d.render_key(&layers);
+        if self.texture.is_some() && self.rendered.as_ref() == Some(&key) {
+            return;
+        }
+
+        let image = d.render(&layers);
+        self.texture = Some(Texture2D::from_rgba8(
+            image.width() as u16,
+            image.height() as u16,
+            image.as_raw(),
+        ));
+        self.rendered = Some(key);
+    }
+
+    pub fn load(&mut self, recognizer: Arc<Recognizer>, folder_bar: Option<Bar>) -> Result<()> {
+        let mut is_loading = self.is_loading.lock().unwrap();
+        if *is_loading {
+            return Ok(());
+        }
+        *is_loading = true;
+
+        let path = self.path.clone();
+        let saved = self.saved.clone();
+        let data = Arc::clone(&self.data);
+        let view = Arc::clone(&self.view);
+        let is_loading = Arc::clone(&self.is_loading);
+        let failed = Arc::clone(&self.failed);
+
+        std::thread::spawn(move || {
+            let mut data = data.lock().unwrap();
+            if data.is_some() {
+                eprintln!("Image data is already loaded for: {}", path.display());
+                return;
+            }
+
+            match ImageData::open(&path, &recognizer, folder_bar) {
+                Ok(mut d) => {
+                    let mut v = View::new(&d);
+                    if let Some(saved) = &saved {
+                        d.apply_project(saved, folder_bar);
+                        v.apply_project(saved);
+                    }
+                    *view.lock().unwrap() = v;
+                    *data = Some(d);
+                }
+                Err(e) => {
+                    eprintln!("{e:#}");
+                    *failed.lock().unwrap() = true;
+                }
+            }
+            *is_loading.lock().unwrap() = false;
+        });
+
+        Ok(())
+    }
+
+    // Rebuilds the color map and temperature grid in the background, merging
+    // only those back so edits made meanwhile are kept
+    pub fn extract(&self, folder_bar: Option<Bar>) {
+        let mut is_loading = self.is_loading.lock().unwrap();
+        if *is_loading {
+            return;
+        }
+        *is_loading = true;
+
+        let data = Arc::clone(&self.data);
+        let is_loading = Arc::clone(&self.is_loading);
+        std::thread::spawn(move || {
+            let snapshot = data.lock().unwrap().clone();
+            if let Some(snapshot) = snapshot {
+                let extraction = snapshot.extraction(folder_bar, true);
+                if let Some(d) = data.lock().unwrap().as_mut() {
+                    d.apply_extraction(extraction);
+                }
+            }
+            *is_loading.lock().unwrap() = false;
+        });
+    }
+}
+
+// Runs `job` over a snapshot of every loaded image on a pool of worker
+// threads, merging back only what it extracted
+pub fn process_all<'a>(
+    images: impl IntoIterator<Item = &'a Image>,
+    job: impl Fn(&ImageData) -> Option<Extraction> + Send + Sync + 'static,
+) {
+    let mut queue = Vec::new();
+    for image in images {
+        let mut is_loading = image.is_loading.lock().unwrap();
+        if *is_loading || image.data.lock().unwrap().is_none() {
+            continue;
+        }
+        *is_loading = true;
+        queue.push((Arc::clone(&image.data), Arc::clone(&image.is_loading)));
+    }
+
+    let workers = std::thread::available_parallelism()
+        .map_or(4, |n| n.get())
+        .min(queue.len());
+    let queue = Arc::new(Mutex::new(queue));
+    let job = Arc::new(job);
+    for _ in 0..workers {
+        let queue = Arc::clone(&queue);
+        let job = Arc::clone(&job);
+        std::thread::spawn(move || {
+            loop {
+                let Some((data, is_loading)) = queue.lock().unwrap().pop() else {
+                    break;
+                };
+                let snapshot = data.lock().unwrap().clone();
+                if let Some(extraction) = snapshot.as_ref().and_then(|d| job(d))
+                    && let Some(d) = data.lock().unwrap().as_mut()
+                {
+                    d.apply_extraction(extraction);
+                }
+                *is_loading.lock().unwrap() = false;
+            }
+        });
+    }
+}
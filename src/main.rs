@@ -1,15 +1,41 @@
+#[cfg(feature = "gui")]
 mod app;
-mod image;
-mod map;
+mod cli;
+#[cfg(feature = "gui")]
+mod loader;
 
-use anyhow::{Context, Result};
+#[cfg(feature = "gui")]
+use anyhow::Context;
+use anyhow::Result;
+#[cfg(feature = "gui")]
 use macroquad::prelude::*;
 
-#[macroquad::main("Thermal Image Viewer")]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if !args.is_empty() || cfg!(not(feature = "gui")) {
+        return cli::run(&args);
+    }
+
+    #[cfg(feature = "gui")]
+    macroquad::Window::new("Thermal Image Viewer", async {
+        if let Err(e) = gui().await {
+            eprintln!("{e:#}");
+            std::process::exit(1);
+        }
+    });
+    Ok(())
+}
+
+#[cfg(feature = "gui")]
+async fn gui() -> Result<()> {
     let mut app = app::App::new();
+    prevent_quit();
 
     loop {
+        if is_quit_requested() {
+            app.quit();
+            return Ok(());
+        }
         app.update().await.context("Failed to update app")?;
         app.draw().await.context("Failed to draw app")?;
         next_frame().await
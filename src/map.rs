@@ -1,8 +1,15 @@
+use serde::{Deserialize, Serialize};
 use std::cmp::Ord;
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Map<F, T>(Vec<(F, T)>);
 
+impl<F: Ord, T: Clone> Default for Map<F, T> {
+    fn default() -> Self {
+        Map::new()
+    }
+}
+
 impl<F: Ord, T: Clone> Map<F, T> {
     pub fn new() -> Map<F, T> {
         Map(Vec::new())
@@ -19,17 +26,266 @@ impl<F: Ord, T: Clone> Map<F, T> {
     pub fn len(&self) -> usize {
         self.0.len()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
 }
 
-impl<F: Clone + Eq + std::hash::Hash, T: Clone> Map<F, T> {
-    pub fn get_closest_by(&self, dist: impl Fn(&F) -> f32) -> Option<T> {
-        self.0
+impl<T: Clone> Map<[u8; 3], T> {
+    pub fn index(&self) -> ColorIndex<T> {
+        let mut nodes: Vec<_> = self
+            .0
             .iter()
-            .min_by(|(a, _), (b, _)| {
-                dist(a)
-                    .partial_cmp(&dist(b))
-                    .unwrap_or(std::cmp::Ordering::Equal)
+            .enumerate()
+            .map(|(i, (k, v))| (*k, v.clone(), i))
+            .collect();
+        build(&mut nodes, 0);
+
+        let mut order = vec![0; nodes.len()];
+        for (n, (_, _, i)) in nodes.iter().enumerate() {
+            order[*i] = n;
+        }
+        let max_segment = self
+            .0
+            .windows(2)
+            .map(|w| (dist2(w[0].0, w[1].0) as f32).sqrt())
+            .fold(0.0, f32::max);
+        ColorIndex {
+            nodes,
+            order,
+            max_segment,
+        }
+    }
+}
+
+// Nearest color lookup over a map's keys, stored as an implicit k-d tree: every
+// slice has its splitting node in the middle, split on channel `depth % 3`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ColorIndex<T> {
+    nodes: Vec<([u8; 3], T, usize)>,
+    order: Vec<usize>,
+    // Longest RGB distance between neighbouring entries of the map
+    max_segment: f32,
+}
+
+impl<T> Default for ColorIndex<T> {
+    fn default() -> Self {
+        ColorIndex {
+            nodes: Vec::new(),
+            order: Vec::new(),
+            max_segment: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Lookup {
+    #[default]
+    Nearest,
+    Interpolated,
+}
+
+fn build<T>(nodes: &mut [([u8; 3], T, usize)], depth: usize) {
+    if nodes.len() <= 1 {
+        return;
+    }
+    let mid = nodes.len() / 2;
+    let axis = depth % 3;
+    nodes.select_nth_unstable_by_key(mid, |n| n.0[axis]);
+    let (left, right) = nodes.split_at_mut(mid);
+    build(left, depth + 1);
+    build(&mut right[1..], depth + 1);
+}
+
+fn dist2(a: [u8; 3], b: [u8; 3]) -> u32 {
+    (0..3)
+        .map(|c| (a[c] as i32 - b[c] as i32).pow(2) as u32)
+        .sum()
+}
+
+impl<T> ColorIndex<T> {
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // Key, value and position in the source map of the entry with the smallest
+    // squared RGB distance, ties going to the entry pushed first
+    pub fn nearest_entry(&self, rgb: [u8; 3]) -> Option<([u8; 3], &T, usize)> {
+        let mut best: Option<(u32, usize, usize)> = None;
+        self.search(0, self.nodes.len(), 0, rgb, &mut best);
+        best.map(|(_, _, n)| {
+            let (k, v, i) = &self.nodes[n];
+            (*k, v, *i)
+        })
+    }
+
+    fn search(
+        &self,
+        lo: usize,
+        hi: usize,
+        depth: usize,
+        rgb: [u8; 3],
+        best: &mut Option<(u32, usize, usize)>,
+    ) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let (key, _, order) = &self.nodes[mid];
+        let d = dist2(*key, rgb);
+        if best.is_none_or(|(bd, bo, _)| (d, *order) < (bd, bo)) {
+            *best = Some((d, *order, mid));
+        }
+
+        let diff = rgb[depth % 3] as i32 - key[depth % 3] as i32;
+        let (near, far) = if diff < 0 {
+            ((lo, mid), (mid + 1, hi))
+        } else {
+            ((mid + 1, hi), (lo, mid))
+        };
+        self.search(near.0, near.1, depth + 1, rgb, best);
+        if best.is_none_or(|(bd, _, _)| (diff * diff) as u32 <= bd) {
+            self.search(far.0, far.1, depth + 1, rgb, best);
+        }
+    }
+
+    // Positions in the source map of every entry at most `radius` from `rgb`
+    fn within(
+        &self,
+        lo: usize,
+        hi: usize,
+        depth: usize,
+        rgb: [u8; 3],
+        radius: f32,
+        found: &mut Vec<usize>,
+    ) {
+        if lo >= hi {
+            return;
+        }
+        let mid = lo + (hi - lo) / 2;
+        let (key, _, order) = &self.nodes[mid];
+        if (dist2(*key, rgb) as f32).sqrt() <= radius {
+            found.push(*order);
+        }
+
+        let diff = rgb[depth % 3] as f32 - key[depth % 3] as f32;
+        if diff <= radius {
+            self.within(lo, mid, depth + 1, rgb, radius, found);
+        }
+        if -diff <= radius {
+            self.within(mid + 1, hi, depth + 1, rgb, radius, found);
+        }
+    }
+}
+
+impl ColorIndex<f32> {
+    // Temperature of `rgb` and its RGB distance from the palette
+    pub fn lookup(&self, rgb: [u8; 3], mode: Lookup) -> Option<(f32, f32)> {
+        let (key, temp, _) = self.nearest_entry(rgb)?;
+        match mode {
+            Lookup::Nearest => Some((*temp, (dist2(key, rgb) as f32).sqrt())),
+            Lookup::Interpolated => {
+                // The closest point on the palette curve is no farther than
+                // the nearest sample, so one end of its segment is within
+                // that plus half the longest segment
+                let sample = |i: usize| {
+                    let (k, v, _) = &self.nodes[self.order[i]];
+                    (*k, *v)
+                };
+                let mut best = (*temp, (dist2(key, rgb) as f32).sqrt());
+                let mut ends = Vec::new();
+                let radius = best.1 + self.max_segment / 2.0;
+                self.within(0, self.nodes.len(), 0, rgb, radius, &mut ends);
+                ends.sort_unstable();
+                for i in ends {
+                    let segments = [i.checked_sub(1), (i + 1 < self.order.len()).then_some(i)];
+                    for j in segments.into_iter().flatten() {
+                        let (a, b) = (sample(j), sample(j + 1));
+                        let (t, d) = project(a.0, b.0, rgb);
+                        if d < best.1 {
+                            best = (a.1 + (b.1 - a.1) * t, d);
+                        }
+                    }
+                }
+                Some(best)
+            }
+        }
+    }
+}
+
+// Position (0.0 at `a`, 1.0 at `b`) and distance of the point on segment a-b
+// closest to `p`
+fn project(a: [u8; 3], b: [u8; 3], p: [u8; 3]) -> (f32, f32) {
+    let ab: [f32; 3] = std::array::from_fn(|c| b[c] as f32 - a[c] as f32);
+    let ap: [f32; 3] = std::array::from_fn(|c| p[c] as f32 - a[c] as f32);
+    let len2: f32 = ab.iter().map(|v| v * v).sum();
+    let t = if len2 > 0.0 {
+        (ab.iter().zip(&ap).map(|(x, y)| x * y).sum::<f32>() / len2).clamp(0.0, 1.0)
+    } else {
+        0.0
+    };
+    let d2: f32 = (0..3).map(|c| (ap[c] - ab[c] * t).powi(2)).sum();
+    (t, d2.sqrt())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Deterministic colors spread over the whole cube
+    fn colors(n: usize, mut seed: u32) -> Vec<[u8; 3]> {
+        (0..n)
+            .map(|_| {
+                seed = seed.wrapping_mul(1103515245).wrapping_add(12345);
+                let [r, g, b, _] = (seed >> 8).to_le_bytes();
+                [r, g, b]
             })
-            .map(|(_, v)| v.clone())
+            .collect()
+    }
+
+    #[test]
+    fn nearest_entry_matches_a_linear_scan() {
+        let mut map = Map::new();
+        // Few distinct channel values, so ties are common
+        for (i, c) in colors(300, 3).into_iter().enumerate() {
+            map.push(c.map(|v| v & 0xe0), i as f32);
+        }
+        let index = map.index();
+        for rgb in colors(1000, 4) {
+            let (pos, (key, value)) = map
+                .0
+                .iter()
+                .enumerate()
+                .min_by_key(|(i, (k, _))| (dist2(*k, rgb), *i))
+                .unwrap();
+            assert_eq!(
+                index.nearest_entry(rgb),
+                Some((*key, value, pos)),
+                "{rgb:?}"
+            );
+        }
+        assert_eq!(
+            Map::<[u8; 3], f32>::new().index().nearest_entry([0; 3]),
+            None
+        );
+    }
+
+    #[test]
+    fn interpolation_finds_the_closest_segment() {
+        let mut map = Map::new();
+        for (i, c) in colors(40, 1).into_iter().enumerate() {
+            map.push(c, i as f32);
+        }
+        let index = map.index();
+        for rgb in colors(500, 2) {
+            let closest = map
+                .0
+                .windows(2)
+                .map(|w| project(w[0].0, w[1].0, rgb).1)
+                .fold(f32::MAX, f32::min);
+            let (_, d) = index.lookup(rgb, Lookup::Interpolated).unwrap();
+            assert_eq!(d, closest, "{rgb:?}");
+        }
     }
 }
@@ -0,0 +1,482 @@
+use anyhow::{Context, Result};
+use image::RgbaImage;
+use std::path::{Path, PathBuf};
+
+use crate::calibration::Bar;
+
+// x, y, width, height in image pixels
+pub type Rect = (u32, u32, u32, u32);
+
+pub const TEMPLATES_DIR: &str = "digits";
+
+const GLYPH_W: u32 = 8;
+const GLYPH_H: u32 = 12;
+const MIN_SCORE: f32 = 0.6;
+
+const FONT: [(char, [u8; 7]); 10] = [
+    ('0', [0x0e, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0e]),
+    ('1', [0x04, 0x0c, 0x04, 0x04, 0x04, 0x04, 0x0e]),
+    ('2', [0x0e, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1f]),
+    ('3', [0x1f, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0e]),
+    ('4', [0x02, 0x06, 0x0a, 0x12, 0x1f, 0x02, 0x02]),
+    ('5', [0x1f, 0x10, 0x1e, 0x01, 0x01, 0x11, 0x0e]),
+    ('6', [0x06, 0x08, 0x10, 0x1e, 0x11, 0x11, 0x0e]),
+    ('7', [0x1f, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0e, 0x11, 0x11, 0x0e, 0x11, 0x11, 0x0e]),
+    ('9', [0x0e, 0x11, 0x11, 0x0f, 0x01, 0x02, 0x0c]),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Reading {
+    pub value: f32,
+    // Digits after the point as printed
+    pub decimals: usize,
+    pub confidence: f32,
+    pub rect: Rect,
+}
+
+#[derive(Debug, Clone)]
+struct Template {
+    ch: char,
+    aspect: f32,
+    cells: Vec<f32>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Recognizer {
+    templates: Vec<Template>,
+}
+
+struct Mask {
+    width: u32,
+    height: u32,
+    bits: Vec<bool>,
+}
+
+impl Mask {
+    // Overlay text is drawn in white, usually with a dark outline
+    fn from_image(img: &RgbaImage, rect: Rect) -> Mask {
+        let (x0, y0, width, height) = rect;
+        let mut bits = Vec::with_capacity((width * height) as usize);
+        for y in y0..y0 + height {
+            for x in x0..x0 + width {
+                let p = img.get_pixel(x, y);
+                let max = p[0].max(p[1]).max(p[2]) as i32;
+                let min = p[0].min(p[1]).min(p[2]) as i32;
+                bits.push(min >= 180 && max - min <= 60);
+            }
+        }
+        Mask {
+            width,
+            height,
+            bits,
+        }
+    }
+
+    fn from_font(rows: &[u8; 7]) -> Mask {
+        let mut bits = Vec::with_capacity(5 * 7);
+        for row in rows {
+            for x in 0..5 {
+                bits.push(row & (0x10 >> x) != 0);
+            }
+        }
+        Mask {
+            width: 5,
+            height: 7,
+            bits,
+        }
+    }
+
+    fn get(&self, x: u32, y: u32) -> bool {
+        self.bits[(y * self.width + x) as usize]
+    }
+
+    fn row_count(&self, y: u32, x0: u32, x1: u32) -> u32 {
+        (x0..x1).filter(|&x| self.get(x, y)).count() as u32
+    }
+
+    fn col_count(&self, x: u32, y0: u32, y1: u32) -> u32 {
+        (y0..y1).filter(|&y| self.get(x, y)).count() as u32
+    }
+
+    // Coverage of each cell of a GLYPH_W x GLYPH_H grid laid over `rect`
+    fn cells(&self, rect: Rect) -> Vec<f32> {
+        let (x0, y0, w, h) = rect;
+        let mut cells = Vec::with_capacity((GLYPH_W * GLYPH_H) as usize);
+        for cy in 0..GLYPH_H {
+            for cx in 0..GLYPH_W {
+                let mut hits = 0;
+                for sy in 0..3 {
+                    for sx in 0..3 {
+                        let fx = (cx as f32 + (sx as f32 + 0.5) / 3.0) / GLYPH_W as f32;
+                        let fy = (cy as f32 + (sy as f32 + 0.5) / 3.0) / GLYPH_H as f32;
+                        let x = x0 + ((fx * w as f32) as u32).min(w - 1);
+                        let y = y0 + ((fy * h as f32) as u32).min(h - 1);
+                        hits += self.get(x, y) as u32;
+                    }
+                }
+                cells.push(hits as f32 / 9.0);
+            }
+        }
+        cells
+    }
+
+    // Bounding boxes of the glyphs on the busiest text line, left to right
+    fn glyphs(&self) -> (u32, u32, Vec<Rect>) {
+        let mut line = (0, 0, 0);
+        let mut y = 0;
+        while y < self.height {
+            if self.row_count(y, 0, self.width) == 0 {
+                y += 1;
+                continue;
+            }
+            let start = y;
+            let mut count = 0;
+            while y < self.height && self.row_count(y, 0, self.width) > 0 {
+                count += self.row_count(y, 0, self.width);
+                y += 1;
+            }
+            if count > line.2 {
+                line = (start, y, count);
+            }
+        }
+
+        let (ly0, ly1, _) = line;
+        let mut glyphs = Vec::new();
+        let mut x = 0;
+        while x < self.width {
+            if self.col_count(x, ly0, ly1) == 0 {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < self.width && self.col_count(x, ly0, ly1) > 0 {
+                x += 1;
+            }
+            let top = (ly0..ly1)
+                .find(|&y| self.row_count(y, start, x) > 0)
+                .unwrap();
+            let bottom = (ly0..ly1)
+                .rfind(|&y| self.row_count(y, start, x) > 0)
+                .unwrap();
+            glyphs.push((start, top, x - start, bottom - top + 1));
+        }
+        (ly0, ly1, glyphs)
+    }
+}
+
+impl Recognizer {
+    pub fn builtin() -> Recognizer {
+        let templates = FONT
+            .iter()
+            .map(|(ch, rows)| {
+                let mask = Mask::from_font(rows);
+                let x0 = (0..5).find(|&x| mask.col_count(x, 0, 7) > 0).unwrap();
+                let x1 = (0..5).rfind(|&x| mask.col_count(x, 0, 7) > 0).unwrap() + 1;
+                let rect = (x0, 0, x1 - x0, 7);
+                Template {
+                    ch: *ch,
+                    aspect: (x1 - x0) as f32 / 7.0,
+                    cells: mask.cells(rect),
+                }
+            })
+            .collect();
+        Recognizer { templates }
+    }
+
+    // Built-in font plus every sample crop in `dir`, named after the value it
+    // shows, e.g. `23.5.png` or `23.5_2.png`
+    pub fn load(dir: &Path) -> Recognizer {
+        let mut recognizer = Recognizer::builtin();
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return recognizer;
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let Some(label) = path
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .and_then(|s| s.split('_').next())
+            else {
+                continue;
+            };
+            match image::open(&path) {
+                Ok(img) => {
+                    if let Err(e) = recognizer.train(&img.into_rgba8(), label) {
+                        eprintln!("Failed to train on {}: {e}", path.display());
+                    }
+                }
+                Err(e) => eprintln!("Failed to open sample {}: {e}", path.display()),
+            }
+        }
+        recognizer
+    }
+
+    pub fn train(&mut self, img: &RgbaImage, label: &str) -> Result<()> {
+        let mask = Mask::from_image(img, (0, 0, img.width(), img.height()));
+        let (ly0, ly1, glyphs) = mask.glyphs();
+        let digits: Vec<char> = label.chars().filter(|c| c.is_ascii_digit()).collect();
+        let boxes: Vec<_> = glyphs
+            .into_iter()
+            .filter(|g| punctuation(*g, ly0, ly1).is_none())
+            .collect();
+        if boxes.len() != digits.len() {
+            anyhow::bail!(
+                "Found {} glyphs for {} digits in {label:?}",
+                boxes.len(),
+                digits.len()
+            );
+        }
+
+        for (ch, rect) in digits.into_iter().zip(boxes) {
+            self.templates.push(Template {
+                ch,
+                aspect: rect.2 as f32 / rect.3 as f32,
+                cells: mask.cells(rect),
+            });
+        }
+        Ok(())
+    }
+
+    fn classify(&self, mask: &Mask, rect: Rect) -> (char, f32) {
+        let cells = mask.cells(rect);
+        let aspect = rect.2 as f32 / rect.3 as f32;
+        self.templates
+            .iter()
+            .map(|t| {
+                let diff = t
+                    .cells
+                    .iter()
+                    .zip(&cells)
+                    .map(|(a, b)| (a - b).abs())
+                    .sum::<f32>()
+                    / cells.len() as f32;
+                (t.ch, 1.0 - diff - 0.5 * (t.aspect - aspect).abs())
+            })
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal))
+            .unwrap_or((' ', 0.0))
+    }
+
+    pub fn read(&self, img: &RgbaImage, rect: Rect) -> Option<Reading> {
+        let mask = Mask::from_image(img, rect);
+        let (ly0, ly1, glyphs) = mask.glyphs();
+
+        let mut chars: Vec<(char, f32, Rect)> = glyphs
+            .into_iter()
+            .map(|g| match punctuation(g, ly0, ly1) {
+                Some(ch) => (ch, 1.0, g),
+                None => {
+                    let (ch, score) = self.classify(&mask, g);
+                    (ch, score, g)
+                }
+            })
+            .collect();
+
+        // Units and stray overlay marks around the number do not match any digit
+        while chars.last().is_some_and(|c| c.1 < MIN_SCORE) {
+            chars.pop();
+        }
+        while chars.first().is_some_and(|c| c.1 < MIN_SCORE) {
+            chars.remove(0);
+        }
+        if !chars.iter().any(|c| c.0.is_ascii_digit()) {
+            return None;
+        }
+
+        let text: String = chars.iter().map(|c| c.0).collect();
+        let value = text.parse::<f32>().ok()?;
+        let decimals = text.split_once('.').map_or(0, |(_, d)| d.len());
+        let confidence = chars.iter().map(|c| c.1).fold(1.0, f32::min).max(0.0);
+        let x0 = chars.iter().map(|c| c.2.0).min()?;
+        let x1 = chars.iter().map(|c| c.2.0 + c.2.2).max()?;
+        Some(Reading {
+            value,
+            decimals,
+            confidence,
+            rect: (rect.0 + x0, rect.1 + ly0, x1 - x0, ly1 - ly0),
+        })
+    }
+
+    // Reads the labels printed next to the hot and cold ends of the bar
+    pub fn read_bar_labels(
+        &self,
+        img: &RgbaImage,
+        bar: &Bar,
+    ) -> (Option<Reading>, Option<Reading>) {
+        let read_end = |end: (u32, u32), inward: (i32, i32)| {
+            label_regions(img, bar, end, inward)
+                .into_iter()
+                .filter_map(|rect| self.read(img, rect))
+                .max_by(|a, b| {
+                    a.confidence
+                        .partial_cmp(&b.confidence)
+                        .unwrap_or(std::cmp::Ordering::Equal)
+                })
+        };
+
+        let dir = (
+            (bar.min.0 as i32 - bar.max.0 as i32).signum(),
+            (bar.min.1 as i32 - bar.max.1 as i32).signum(),
+        );
+        (read_end(bar.max, dir), read_end(bar.min, (-dir.0, -dir.1)))
+    }
+}
+
+// Saves the crop of a reading as a sample of `value`, written with as many
+// digits as the label shows so training finds a glyph for each
+pub fn save_sample(img: &RgbaImage, reading: &Reading, value: f32) -> Result<PathBuf> {
+    let dir = Path::new(TEMPLATES_DIR);
+    std::fs::create_dir_all(dir).context("Failed to create templates directory")?;
+
+    let label = sample_label(value, reading.decimals);
+    let mut path = dir.join(format!("{label}.png"));
+    let mut n = 1;
+    while path.exists() {
+        path = dir.join(format!("{label}_{n}.png"));
+        n += 1;
+    }
+
+    let (x, y, w, h) = reading.rect;
+    image::imageops::crop_imm(img, x.saturating_sub(2), y.saturating_sub(2), w + 4, h + 4)
+        .to_image()
+        .save(&path)
+        .context(format!("Failed to save sample {}", path.display()))?;
+    Ok(path)
+}
+
+fn sample_label(value: f32, decimals: usize) -> String {
+    format!("{value:.decimals$}")
+}
+
+fn punctuation(glyph: Rect, ly0: u32, ly1: u32) -> Option<char> {
+    let (_, y, w, h) = glyph;
+    let line = ly1 - ly0;
+    if h * 10 > line * 3 {
+        return None;
+    }
+    if y + h + line / 6 >= ly1 {
+        Some('.')
+    } else if w >= h {
+        Some('-')
+    } else {
+        None
+    }
+}
+
+// Boxes on every side of a bar end except the one the bar continues into
+fn label_regions(img: &RgbaImage, bar: &Bar, end: (u32, u32), inward: (i32, i32)) -> Vec<Rect> {
+    let scale = (img.height() / 240).max(1) as i32;
+    let (long, short) = (48 * scale, 14 * scale);
+    let half = bar.width as i32 / 2 + 3;
+    let (x, y) = (end.0 as i32, end.1 as i32);
+
+    let candidates = [
+        (
+            (0, -1),
+            (x - long, y - half - 2 * short, 2 * long, 2 * short),
+        ),
+        ((0, 1), (x - long, y + half, 2 * long, 2 * short)),
+        (
+            (-1, 0),
+            (x - half - 2 * long, y - short, 2 * long, 2 * short),
+        ),
+        ((1, 0), (x + half, y - short, 2 * long, 2 * short)),
+    ];
+
+    candidates
+        .into_iter()
+        .filter(|(side, _)| *side != inward)
+        .filter_map(|(_, (x, y, w, h))| {
+            let x0 = x.clamp(0, img.width() as i32);
+            let y0 = y.clamp(0, img.height() as i32);
+            let x1 = (x + w).clamp(0, img.width() as i32);
+            let y1 = (y + h).clamp(0, img.height() as i32);
+            (x1 - x0 >= 4 && y1 - y0 >= 4).then_some((
+                x0 as u32,
+                y0 as u32,
+                (x1 - x0) as u32,
+                (y1 - y0) as u32,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgba;
+
+    use super::*;
+
+    const SCALE: u32 = 2;
+
+    // White text in the built-in font on black, each character 6 columns wide
+    fn render(text: &str) -> RgbaImage {
+        let width = (text.len() as u32 * 6 + 4) * SCALE;
+        let mut img = RgbaImage::from_pixel(width, 11 * SCALE, Rgba([0, 0, 0, 255]));
+        for (i, ch) in text.chars().enumerate() {
+            let rows = match ch {
+                '.' => [0, 0, 0, 0, 0, 0, 0x04],
+                '-' => [0, 0, 0, 0x0e, 0, 0, 0],
+                _ => FONT.iter().find(|(c, _)| *c == ch).unwrap().1,
+            };
+            for (y, row) in rows.iter().enumerate() {
+                for x in 0..5 {
+                    if row & (0x10 >> x) == 0 {
+                        continue;
+                    }
+                    let px = (2 + i as u32 * 6 + x) * SCALE;
+                    let py = (2 + y as u32) * SCALE;
+                    for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                        img.put_pixel(px + dx, py + dy, Rgba([255, 255, 255, 255]));
+                    }
+                }
+            }
+        }
+        img
+    }
+
+    fn whole(img: &RgbaImage) -> Rect {
+        (0, 0, img.width(), img.height())
+    }
+
+    #[test]
+    fn glyphs_split_digits_and_punctuation() {
+        let img = render("-12.5");
+        let mask = Mask::from_image(&img, whole(&img));
+        let (ly0, ly1, glyphs) = mask.glyphs();
+        assert_eq!((ly0, ly1), (2 * SCALE, 9 * SCALE));
+        assert_eq!(glyphs.len(), 5);
+
+        let kinds: Vec<_> = glyphs.iter().map(|g| punctuation(*g, ly0, ly1)).collect();
+        assert_eq!(kinds, [Some('-'), None, None, Some('.'), None]);
+        assert!(glyphs.windows(2).all(|w| w[0].0 + w[0].2 < w[1].0));
+    }
+
+    #[test]
+    fn builtin_font_reads_labels() {
+        let recognizer = Recognizer::builtin();
+        for (text, value, decimals) in [("23.5", 23.5, 1), ("-4.0", -4.0, 1), ("1078", 1078.0, 0)] {
+            let img = render(text);
+            let reading = recognizer.read(&img, whole(&img)).unwrap();
+            assert_eq!(reading.value, value, "{text}");
+            assert_eq!(reading.decimals, decimals, "{text}");
+            assert!(reading.confidence > 0.9, "{text}");
+        }
+
+        let blank = RgbaImage::new(20, 20);
+        assert_eq!(recognizer.read(&blank, whole(&blank)), None);
+    }
+
+    #[test]
+    fn samples_train_on_every_digit() {
+        let img = render("30.0");
+        let mut recognizer = Recognizer::builtin();
+        let reading = recognizer.read(&img, whole(&img)).unwrap();
+
+        let label = sample_label(reading.value, reading.decimals);
+        assert_eq!(label, "30.0");
+        recognizer.train(&img, &label).unwrap();
+        assert!(recognizer.train(&img, "30").is_err());
+        assert_eq!(recognizer.templates.len(), FONT.len() + 3);
+    }
+}
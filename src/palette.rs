@@ -0,0 +1,185 @@
+use anyhow::{Context, Result, bail};
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::map::Map;
+
+pub const PALETTES_DIR: &str = "palettes";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Palette {
+    pub name: String,
+    pub stops: Vec<[u8; 3]>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Recolor {
+    pub palette: Palette,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl Palette {
+    pub fn new(name: &str, stops: &[[u8; 3]]) -> Palette {
+        Palette {
+            name: name.to_string(),
+            stops: stops.to_vec(),
+        }
+    }
+
+    pub fn builtin() -> Vec<Palette> {
+        vec![
+            Palette::new(
+                "Iron",
+                &[
+                    [0, 0, 0],
+                    [30, 0, 90],
+                    [100, 0, 150],
+                    [180, 20, 140],
+                    [230, 70, 60],
+                    [250, 140, 0],
+                    [255, 210, 40],
+                    [255, 255, 255],
+                ],
+            ),
+            Palette::new(
+                "Rainbow",
+                &[
+                    [0, 0, 0],
+                    [0, 0, 255],
+                    [0, 255, 255],
+                    [0, 255, 0],
+                    [255, 255, 0],
+                    [255, 0, 0],
+                    [255, 255, 255],
+                ],
+            ),
+            Palette::new("Grayscale", &[[0, 0, 0], [255, 255, 255]]),
+            Palette::new(
+                "Lava",
+                &[
+                    [0, 0, 0],
+                    [20, 20, 120],
+                    [0, 120, 140],
+                    [200, 40, 40],
+                    [255, 120, 0],
+                    [255, 220, 0],
+                    [255, 255, 255],
+                ],
+            ),
+            Palette::new(
+                "Arctic",
+                &[
+                    [0, 0, 40],
+                    [0, 60, 160],
+                    [60, 160, 220],
+                    [200, 230, 250],
+                    [250, 200, 120],
+                    [240, 120, 30],
+                    [200, 40, 0],
+                ],
+            ),
+        ]
+    }
+
+    // Built-in palettes followed by the ones found in `dir`
+    pub fn library(dir: &Path) -> Vec<Palette> {
+        let mut palettes = Palette::builtin();
+        let Ok(entries) = std::fs::read_dir(dir) else {
+            return palettes;
+        };
+        let mut paths: Vec<_> = entries.flatten().map(|e| e.path()).collect();
+        paths.sort();
+        for path in paths {
+            match Palette::load(&path) {
+                Ok(palette) => palettes.push(palette),
+                Err(e) => eprintln!("Failed to load palette {}: {e:#}", path.display()),
+            }
+        }
+        palettes
+    }
+
+    // One color per line, cold to hot, as `#rrggbb` or `r g b`. Lines starting
+    // with `;` are comments.
+    pub fn load(path: &Path) -> Result<Palette> {
+        let text = std::fs::read_to_string(path).context("Failed to read palette file")?;
+        let mut stops = Vec::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+            stops.push(parse_color(line).context(format!("Invalid color on line {}", n + 1))?);
+        }
+        if stops.len() < 2 {
+            bail!("A palette needs at least two colors");
+        }
+
+        let name = path
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .to_string();
+        Ok(Palette { name, stops })
+    }
+
+    // Color at `t`, 0.0 being the coldest and 1.0 the hottest
+    pub fn color_at(&self, t: f32) -> [u8; 3] {
+        let x = t.clamp(0.0, 1.0) * (self.stops.len() - 1) as f32;
+        let i = (x as usize).min(self.stops.len() - 2);
+        let f = x - i as f32;
+        let (a, b) = (self.stops[i], self.stops[i + 1]);
+        std::array::from_fn(|c| (a[c] as f32 + (b[c] as f32 - a[c] as f32) * f).round() as u8)
+    }
+
+    // Samples the gradient every `step` degrees between the two temperatures
+    pub fn to_map(&self, min_temp: f32, max_temp: f32, step: f32) -> Map<[u8; 3], f32> {
+        let mut map = Map::new();
+        if step <= 0.0 || max_temp <= min_temp {
+            return map;
+        }
+
+        let steps = ((max_temp - min_temp) / step).round() as u32;
+        for i in 0..=steps {
+            let t = i as f32 / steps.max(1) as f32;
+            map.push(self.color_at(t), min_temp + (max_temp - min_temp) * t);
+        }
+        map
+    }
+
+    // Paints every pixel with a temperature in this palette, stretched over
+    // `min..=max`. Pixels without a temperature keep their original color.
+    pub fn render(&self, base: &RgbaImage, temperatures: &[f32], min: f32, max: f32) -> RgbaImage {
+        let mut image = base.clone();
+        let range = (max - min).max(f32::EPSILON);
+        for (pixel, temp) in image.pixels_mut().zip(temperatures) {
+            if temp.is_nan() {
+                continue;
+            }
+            let c = self.color_at((temp - min) / range);
+            *pixel = Rgba([c[0], c[1], c[2], pixel[3]]);
+        }
+        image
+    }
+}
+
+fn parse_color(s: &str) -> Result<[u8; 3]> {
+    if let Some(hex) = s.strip_prefix('#') {
+        if hex.len() != 6 || !hex.is_ascii() {
+            bail!("Expected #rrggbb, got {s:?}");
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16);
+        return Ok([channel(0)?, channel(2)?, channel(4)?]);
+    }
+
+    let parts: Vec<u8> = s
+        .split(|c: char| c.is_whitespace() || c == ',')
+        .filter(|p| !p.is_empty())
+        .map(|p| p.parse::<u8>())
+        .collect::<Result<_, _>>()?;
+    match parts[..] {
+        [r, g, b] => Ok([r, g, b]),
+        _ => bail!("Expected three channels, got {s:?}"),
+    }
+}
@@ -0,0 +1,149 @@
+use anyhow::{Context, Result, bail};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, path::Path};
+
+use crate::{calibration::Bar, image::Settings};
+
+// Kept inside the image folder so it moves along with it
+pub const PROJECT_FILE: &str = "thermal-maps.json";
+// 2 names the camera of radiometric images
+const VERSION: u32 = 2;
+
+/// Edits made to the images of a folder, keyed by file name
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Project {
+    pub version: u32,
+    pub folder_bar: Option<Bar>,
+    pub images: BTreeMap<String, ImageProject>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ImageProject {
+    pub settings: Settings,
+    #[serde(default)]
+    pub notes: String,
+}
+
+impl Project {
+    pub fn new(folder_bar: Option<Bar>) -> Project {
+        Project {
+            version: VERSION,
+            folder_bar,
+            images: BTreeMap::new(),
+        }
+    }
+
+    // None when the folder has no project file yet
+    pub fn load(dir: &Path) -> Result<Option<Project>> {
+        let path = dir.join(PROJECT_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let text =
+            std::fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+        let project: Project =
+            serde_json::from_str(&text).context(format!("Failed to parse {}", path.display()))?;
+        // Older versions are read as they are, newer ones may mean something else
+        if project.version > VERSION {
+            bail!(
+                "{} is from a newer version (project version {})",
+                path.display(),
+                project.version
+            );
+        }
+        project
+            .check()
+            .context(format!("Failed to read {}", path.display()))?;
+        Ok(Some(project))
+    }
+
+    // Palettes and bars are used as they are, so ones the viewer could not
+    // have made are refused here rather than crashing later
+    fn check(&self) -> Result<()> {
+        if self.folder_bar.is_some_and(|b| b.width == 0) {
+            bail!("The folder scale bar has no width");
+        }
+        for (name, image) in &self.images {
+            let s = &image.settings;
+            if s.bar.is_some_and(|b| b.width == 0) {
+                bail!("The scale bar of {name} has no width");
+            }
+            for palette in s.palette.iter().chain([&s.recolor.palette]) {
+                if palette.stops.len() < 2 {
+                    bail!(
+                        "Palette {} of {name} has fewer than two colors",
+                        palette.name
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Writes to a temporary file first so a crash never leaves half a project
+    pub fn save(&self, dir: &Path) -> Result<()> {
+        let path = dir.join(PROJECT_FILE);
+        let tmp = path.with_extension("json.tmp");
+        let text = serde_json::to_string_pretty(self).context("Failed to serialize project")?;
+        std::fs::write(&tmp, text).context(format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, &path).context(format!("Failed to write {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ImageData, View, ocr::Recognizer, palette::Palette};
+    use image::RgbaImage;
+
+    #[test]
+    fn load_refuses_newer_versions() {
+        let dir = std::env::temp_dir().join(format!("thermal-maps-project-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut project = Project::new(None);
+        project.save(&dir).unwrap();
+        assert_eq!(Project::load(&dir).unwrap().unwrap().version, VERSION);
+
+        project.version = VERSION + 1;
+        project.save(&dir).unwrap();
+        assert!(Project::load(&dir).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_refuses_unusable_bars_and_palettes() {
+        let dir = std::env::temp_dir().join(format!("thermal-maps-check-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let mut project = Project::new(Some(Bar::default()));
+        project.save(&dir).unwrap();
+        assert!(Project::load(&dir).is_ok());
+
+        project.folder_bar = Some(Bar {
+            width: 0,
+            ..Bar::default()
+        });
+        project.save(&dir).unwrap();
+        assert!(Project::load(&dir).is_err());
+
+        let path = dir.join("a.png");
+        RgbaImage::new(8, 8).save(&path).unwrap();
+        let d = ImageData::open(&path, &Recognizer::builtin(), None).unwrap();
+        let mut settings = d.settings(&View::new(&d));
+        settings.palette = Some(Palette::new("Flat", &[[0, 0, 0]]));
+        project.folder_bar = None;
+        project.images.insert(
+            "a.png".to_string(),
+            ImageProject {
+                settings,
+                notes: String::new(),
+            },
+        );
+        project.save(&dir).unwrap();
+        assert!(Project::load(&dir).is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,182 @@
+use image::{ImageBuffer, Luma, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+
+use crate::{dji, field::TemperatureField, flir};
+
+pub const KELVIN: f32 = 273.15;
+
+/// What turns the raw values of a camera into temperatures
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Camera {
+    Flir(flir::CameraInfo),
+    Dji(dji::Parameters),
+    Counts(Scale),
+}
+
+/// How the counts of a plain 16-bit frame map to temperatures
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Scale {
+    // Hundredths of a kelvin, as Lepton and Seek cameras write them
+    Centikelvin,
+    // count * gain + offset in °C
+    Linear { gain: f32, offset: f32 },
+}
+
+/// Raw sensor values of a radiometric image
+#[derive(Debug, Clone, PartialEq)]
+pub struct Radiometric {
+    pub width: u32,
+    pub height: u32,
+    pub raw: Vec<u16>,
+    pub camera: Camera,
+}
+
+impl Camera {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Camera::Flir(_) => "FLIR",
+            Camera::Dji(_) => "DJI",
+            Camera::Counts(_) => "16-bit",
+        }
+    }
+
+    // Counts are already the temperature of the object
+    pub fn emissivity(&self) -> f32 {
+        match self {
+            Camera::Flir(c) => c.emissivity,
+            Camera::Dji(p) => p.emissivity,
+            Camera::Counts(_) => 1.0,
+        }
+    }
+
+    pub fn converter(&self) -> Box<dyn Fn(u16) -> f32 + '_> {
+        match self {
+            Camera::Flir(c) => Box::new(c.converter()),
+            Camera::Dji(p) => Box::new(p.converter()),
+            Camera::Counts(Scale::Centikelvin) => Box::new(|raw| raw as f32 / 100.0 - KELVIN),
+            Camera::Counts(Scale::Linear { gain, offset }) => {
+                Box::new(move |raw| raw as f32 * gain + offset)
+            }
+        }
+    }
+}
+
+impl Scale {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scale::Centikelvin => "Centikelvin",
+            Scale::Linear { .. } => "Gain and offset",
+        }
+    }
+}
+
+impl Radiometric {
+    // Counts of a grayscale frame, taken as centikelvin until told otherwise
+    pub fn from_counts(frame: &ImageBuffer<Luma<u16>, Vec<u16>>) -> Radiometric {
+        Radiometric {
+            width: frame.width(),
+            height: frame.height(),
+            raw: frame.as_raw().clone(),
+            camera: Camera::Counts(Scale::Centikelvin),
+        }
+    }
+
+    // Grayscale stretched over the counts in use, which 8 bits can show
+    pub fn preview(&self) -> RgbaImage {
+        let lo = self.raw.iter().copied().min().unwrap_or(0) as f32;
+        let hi = self.raw.iter().copied().max().unwrap_or(0) as f32;
+        let range = (hi - lo).max(1.0);
+        RgbaImage::from_fn(self.width, self.height, |x, y| {
+            let count = self.raw[(y * self.width + x) as usize] as f32;
+            let v = ((count - lo) / range * 255.0).round() as u8;
+            Rgba([v, v, v, 255])
+        })
+    }
+
+    /// Temperatures for an image of `width` x `height`, which may be larger than
+    /// the sensor
+    pub fn temperatures(&self, width: u32, height: u32) -> TemperatureField {
+        let mut field = TemperatureField::new(width, height);
+        if self.width == 0 || self.height == 0 {
+            return field;
+        }
+
+        let convert = self.camera.converter();
+        for y in 0..height {
+            let sy = (y as u64 * self.height as u64 / height as u64) as u32;
+            for x in 0..width {
+                let sx = (x as u64 * self.width as u64 / width as u64) as u32;
+                let raw = self.raw[(sy * self.width + sx) as usize];
+                field.values[(y * width + x) as usize] = convert(raw);
+            }
+        }
+        field
+    }
+}
+
+/// Sensor data embedded in a JPEG of `width` x `height` by any camera we know,
+/// None when there is none or it cannot be used
+pub fn read(bytes: &[u8], width: u32, height: u32) -> Option<Radiometric> {
+    match flir::read(bytes) {
+        Ok(Some(r)) => return Some(r),
+        Ok(None) => {}
+        // The JPEG itself is still fine to open
+        Err(e) => eprintln!("Ignoring unusable FLIR data: {e:#}"),
+    }
+    dji::read(bytes, width, height)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: f32, b: f32) {
+        assert!((a - b).abs() < 0.01, "{a} is not {b}");
+    }
+
+    #[test]
+    fn counts_scales() {
+        let convert = Camera::Counts(Scale::Centikelvin).converter();
+        assert_close(convert(29315), 20.0);
+        assert_close(convert(27315), 0.0);
+
+        let linear = Camera::Counts(Scale::Linear {
+            gain: 0.1,
+            offset: -40.0,
+        });
+        let convert = linear.converter();
+        assert_close(convert(0), -40.0);
+        assert_close(convert(500), 10.0);
+    }
+
+    #[test]
+    fn temperatures_scale_up_the_sensor() {
+        let frame = ImageBuffer::from_raw(2, 1, vec![27315, 29315]).unwrap();
+        let r = Radiometric::from_counts(&frame);
+        let field = r.temperatures(4, 2);
+        assert_eq!((field.width, field.height), (4, 2));
+        for (value, expected) in field.values.iter().zip([0.0, 0.0, 20.0, 20.0].repeat(2)) {
+            assert_close(*value, expected);
+        }
+    }
+
+    #[test]
+    fn read_falls_back_after_unusable_flir_data() {
+        let raw: Vec<u8> = [1000u16, 2000, 3000, 4000]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let mut bytes = vec![0xff, 0xd8];
+        for (marker, payload) in [(0xe1, &b"FLIR\0\x01\x00\x00garbage"[..]), (0xe3, &raw)] {
+            bytes.extend([0xff, marker]);
+            bytes.extend((payload.len() as u16 + 2).to_be_bytes());
+            bytes.extend_from_slice(payload);
+        }
+        bytes.extend([0xff, 0xd9]);
+
+        let r = read(&bytes, 2, 2).unwrap();
+        assert_eq!(r.raw, vec![1000, 2000, 3000, 4000]);
+        assert!(matches!(r.camera, Camera::Dji(_)));
+        assert_eq!(read(&bytes, 3, 2), None);
+    }
+}
@@ -0,0 +1,102 @@
+use image::{Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::{filter::Filter, isotherm::Isotherm, palette::Recolor, roi::Roi, spot::Spot};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Layer {
+    Palette,
+    Filter,
+    Isotherms,
+    // ROIs and spots. The viewer draws them over its texture itself, with
+    // their names, saved images get plain outlines and crosses.
+    Annotations,
+}
+
+/// One step of the render pipeline, applied in list order on top of the raw image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Operation {
+    pub layer: Layer,
+    pub enabled: bool,
+}
+
+/// Everything a rendered image depends on, to tell when it is out of date
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderKey {
+    pub layers: Vec<Operation>,
+    pub recolor: Recolor,
+    pub filter: Filter,
+    pub isotherms: Vec<Isotherm>,
+    pub rois: Vec<Roi>,
+    pub spots: Vec<Spot>,
+    pub revision: u32,
+}
+
+impl Layer {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layer::Palette => "Palette remap",
+            Layer::Filter => "Filter",
+            Layer::Isotherms => "Isotherms",
+            Layer::Annotations => "Annotations",
+        }
+    }
+}
+
+pub fn default_layers() -> Vec<Operation> {
+    [
+        (Layer::Palette, false),
+        (Layer::Filter, false),
+        (Layer::Isotherms, true),
+        (Layer::Annotations, true),
+    ]
+    .into_iter()
+    .map(|(layer, enabled)| Operation { layer, enabled })
+    .collect()
+}
+
+// Outlines every region in green and marks every spot with a white cross,
+// leaving the pixel of the spot itself visible
+pub fn draw_annotations(image: &mut RgbaImage, rois: &[Roi], spots: &[Spot]) {
+    let (w, h) = image.dimensions();
+    for roi in rois {
+        let inside: HashSet<(u32, u32)> = roi.shape.pixels(w, h).collect();
+        for &(x, y) in &inside {
+            let edge = x == 0
+                || y == 0
+                || x + 1 == w
+                || y + 1 == h
+                || [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
+                    .iter()
+                    .any(|p| !inside.contains(p));
+            if edge {
+                image.put_pixel(x, y, Rgba([0, 255, 0, 255]));
+            }
+        }
+    }
+
+    for spot in spots {
+        let (x, y) = (spot.position.0 as i64, spot.position.1 as i64);
+        for d in 2..=5 {
+            for (px, py) in [(x - d, y), (x + d, y), (x, y - d), (x, y + d)] {
+                if px >= 0 && py >= 0 && px < w as i64 && py < h as i64 {
+                    image.put_pixel(px as u32, py as u32, Rgba([255, 255, 255, 255]));
+                }
+            }
+        }
+    }
+}
+
+// Paints `overlay` over `base` using the overlay's alpha
+pub fn blend(base: &mut RgbaImage, overlay: &RgbaImage) {
+    for (b, o) in base.pixels_mut().zip(overlay.pixels()) {
+        let a = o[3] as u32;
+        if a == 0 {
+            continue;
+        }
+        for c in 0..3 {
+            b[c] = ((o[c] as u32 * a + b[c] as u32 * (255 - a)) / 255) as u8;
+        }
+    }
+}
@@ -0,0 +1,230 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{field::TemperatureField, stats::Stats};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ShapeKind {
+    Rectangle,
+    Ellipse,
+    Polygon,
+}
+
+// Coordinates are in image pixels, (0, 0) being the top left corner of the
+// first pixel. A pixel belongs to a shape when its center does.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Shape {
+    Rectangle {
+        min: (f32, f32),
+        max: (f32, f32),
+    },
+    Ellipse {
+        center: (f32, f32),
+        radius: (f32, f32),
+    },
+    Polygon(Vec<(f32, f32)>),
+}
+
+/// A named region of interest over an image
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Roi {
+    pub name: String,
+    pub shape: Shape,
+}
+
+impl ShapeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShapeKind::Rectangle => "Rectangle",
+            ShapeKind::Ellipse => "Ellipse",
+            ShapeKind::Polygon => "Polygon",
+        }
+    }
+}
+
+impl Shape {
+    // Rectangle covering both pixels and everything between them
+    pub fn rectangle(a: (u32, u32), b: (u32, u32)) -> Shape {
+        Shape::Rectangle {
+            min: (a.0.min(b.0) as f32, a.1.min(b.1) as f32),
+            max: (a.0.max(b.0) as f32 + 1.0, a.1.max(b.1) as f32 + 1.0),
+        }
+    }
+
+    // Ellipse inscribed in the rectangle between both pixels
+    pub fn ellipse(a: (u32, u32), b: (u32, u32)) -> Shape {
+        let Shape::Rectangle { min, max } = Shape::rectangle(a, b) else {
+            unreachable!()
+        };
+        Shape::Ellipse {
+            center: ((min.0 + max.0) / 2.0, (min.1 + max.1) / 2.0),
+            radius: ((max.0 - min.0) / 2.0, (max.1 - min.1) / 2.0),
+        }
+    }
+
+    // Polygon through the centers of the given pixels
+    pub fn polygon(points: &[(u32, u32)]) -> Shape {
+        Shape::Polygon(
+            points
+                .iter()
+                .map(|p| (p.0 as f32 + 0.5, p.1 as f32 + 0.5))
+                .collect(),
+        )
+    }
+
+    pub fn kind(&self) -> ShapeKind {
+        match self {
+            Shape::Rectangle { .. } => ShapeKind::Rectangle,
+            Shape::Ellipse { .. } => ShapeKind::Ellipse,
+            Shape::Polygon(_) => ShapeKind::Polygon,
+        }
+    }
+
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        match self {
+            Shape::Rectangle { min, max } => x >= min.0 && y >= min.1 && x < max.0 && y < max.1,
+            Shape::Ellipse { center, radius } => {
+                if radius.0 <= 0.0 || radius.1 <= 0.0 {
+                    return false;
+                }
+                let dx = (x - center.0) / radius.0;
+                let dy = (y - center.1) / radius.1;
+                dx * dx + dy * dy <= 1.0
+            }
+            // Even-odd rule
+            Shape::Polygon(points) => {
+                let mut inside = false;
+                for (i, a) in points.iter().enumerate() {
+                    let b = points[(i + 1) % points.len()];
+                    if (a.1 > y) != (b.1 > y) && x < a.0 + (y - a.1) * (b.0 - a.0) / (b.1 - a.1) {
+                        inside = !inside;
+                    }
+                }
+                inside
+            }
+        }
+    }
+
+    // Smallest and largest corner of the bounding box
+    pub fn bounds(&self) -> ((f32, f32), (f32, f32)) {
+        match self {
+            Shape::Rectangle { min, max } => (*min, *max),
+            Shape::Ellipse { center, radius } => (
+                (center.0 - radius.0, center.1 - radius.1),
+                (center.0 + radius.0, center.1 + radius.1),
+            ),
+            Shape::Polygon(points) => points.iter().fold(
+                ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN)),
+                |(min, max), p| {
+                    (
+                        (min.0.min(p.0), min.1.min(p.1)),
+                        (max.0.max(p.0), max.1.max(p.1)),
+                    )
+                },
+            ),
+        }
+    }
+
+    // Every pixel of a `width` x `height` image inside the shape
+    pub fn pixels(&self, width: u32, height: u32) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let (min, max) = self.bounds();
+        let x0 = min.0.max(0.0) as u32;
+        let y0 = min.1.max(0.0) as u32;
+        let x1 = (max.0.ceil().max(0.0) as u32).min(width);
+        let y1 = (max.1.ceil().max(0.0) as u32).min(height);
+        (y0..y1)
+            .flat_map(move |y| (x0..x1).map(move |x| (x, y)))
+            .filter(|&(x, y)| self.contains(x as f32 + 0.5, y as f32 + 0.5))
+    }
+}
+
+impl Roi {
+    // First free name like "Ellipse 2" for a new shape of this kind
+    pub fn next_name(rois: &[Roi], kind: ShapeKind) -> String {
+        (1..)
+            .map(|n| format!("{} {n}", kind.name()))
+            .find(|name| !rois.iter().any(|r| &r.name == name))
+            .unwrap()
+    }
+
+    // Number of pixels inside the region, with or without a temperature
+    pub fn area(&self, field: &TemperatureField) -> usize {
+        self.shape.pixels(field.width, field.height).count()
+    }
+
+    // Temperatures of the pixels inside the region that have one
+    pub fn temperatures<'a>(
+        &'a self,
+        field: &'a TemperatureField,
+    ) -> impl Iterator<Item = f32> + 'a {
+        self.shape
+            .pixels(field.width, field.height)
+            .filter_map(|(x, y)| field.get(x, y))
+    }
+
+    pub fn stats(&self, field: &TemperatureField) -> Option<Stats> {
+        Stats::from_values(self.temperatures(field))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pixels(shape: &Shape, width: u32, height: u32) -> Vec<(u32, u32)> {
+        shape.pixels(width, height).collect()
+    }
+
+    #[test]
+    fn rectangles_cover_both_corner_pixels() {
+        let shape = Shape::rectangle((3, 2), (1, 1));
+        assert_eq!(
+            pixels(&shape, 10, 10),
+            [(1, 1), (2, 1), (3, 1), (1, 2), (2, 2), (3, 2)]
+        );
+        assert!(shape.contains(1.0, 1.0));
+        assert!(!shape.contains(4.0, 2.5));
+
+        // Clipped to the image
+        let shape = Shape::rectangle((8, 8), (12, 12));
+        assert_eq!(pixels(&shape, 10, 10), [(8, 8), (9, 8), (8, 9), (9, 9)]);
+    }
+
+    #[test]
+    fn ellipses_leave_out_the_corners() {
+        let shape = Shape::ellipse((0, 0), (4, 4));
+        let inside = pixels(&shape, 10, 10);
+        assert_eq!(inside.len(), 21);
+        for corner in [(0, 0), (4, 0), (0, 4), (4, 4)] {
+            assert!(!inside.contains(&corner));
+        }
+        assert!(inside.contains(&(2, 2)) && inside.contains(&(0, 2)));
+
+        let flat = Shape::Ellipse {
+            center: (2.5, 2.5),
+            radius: (2.5, 0.0),
+        };
+        assert!(!flat.contains(2.5, 2.5));
+        assert!(pixels(&flat, 10, 10).is_empty());
+    }
+
+    #[test]
+    fn polygons_follow_the_even_odd_rule() {
+        // An L, with the square at its inner corner left out
+        let shape = Shape::Polygon(vec![
+            (0.0, 0.0),
+            (4.0, 0.0),
+            (4.0, 2.0),
+            (2.0, 2.0),
+            (2.0, 4.0),
+            (0.0, 4.0),
+        ]);
+        let inside = pixels(&shape, 10, 10);
+        assert_eq!(inside.len(), 12);
+        assert!(inside.contains(&(3, 1)) && inside.contains(&(1, 3)));
+        assert!(!inside.contains(&(3, 3)));
+
+        // Points outside the image are fine
+        let shape = Shape::Polygon(vec![(-2.0, -2.0), (2.0, -2.0), (2.0, 2.0), (-2.0, 2.0)]);
+        assert_eq!(pixels(&shape, 10, 10), [(0, 0), (1, 0), (0, 1), (1, 1)]);
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+use crate::field::TemperatureField;
+
+/// A named pixel whose temperature is read out
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Spot {
+    pub name: String,
+    pub position: (u32, u32),
+}
+
+impl Spot {
+    // Sp1, Sp2, ... skipping names that are taken
+    pub fn next_name(spots: &[Spot]) -> String {
+        (1..)
+            .map(|n| format!("Sp{n}"))
+            .find(|name| !spots.iter().any(|s| &s.name == name))
+            .unwrap()
+    }
+
+    pub fn temperature(&self, field: &TemperatureField) -> Option<f32> {
+        field.get(self.position.0, self.position.1)
+    }
+}
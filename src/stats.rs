@@ -0,0 +1,111 @@
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub count: usize,
+    pub min: f32,
+    pub max: f32,
+    pub mean: f32,
+    pub std_dev: f32,
+}
+
+impl Stats {
+    // Statistics of the values that are not NaN, None when there are none
+    pub fn from_values(values: impl IntoIterator<Item = f32>) -> Option<Stats> {
+        let mut count = 0;
+        let mut min = f32::INFINITY;
+        let mut max = f32::NEG_INFINITY;
+        let mut sum = 0.0f64;
+        let mut sum_sq = 0.0f64;
+        for v in values.into_iter().filter(|v| !v.is_nan()) {
+            count += 1;
+            min = min.min(v);
+            max = max.max(v);
+            sum += v as f64;
+            sum_sq += v as f64 * v as f64;
+        }
+        if count == 0 {
+            return None;
+        }
+
+        let mean = sum / count as f64;
+        let variance = (sum_sq / count as f64 - mean * mean).max(0.0);
+        Some(Stats {
+            count,
+            min,
+            max,
+            mean: mean as f32,
+            std_dev: variance.sqrt() as f32,
+        })
+    }
+}
+
+// More bins than this are merged, however narrow they were asked to be
+pub const MAX_BINS: usize = 1024;
+
+// Counts per temperature bin, the first bin starting at `min`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Histogram {
+    pub min: f32,
+    pub bin_width: f32,
+    pub counts: Vec<usize>,
+}
+
+impl Histogram {
+    // Values that are NaN or outside `min..=max` are left out. The bins get
+    // wider when `bin_width` would need more than `MAX_BINS` of them.
+    pub fn new(
+        values: impl IntoIterator<Item = f32>,
+        min: f32,
+        max: f32,
+        bin_width: f32,
+    ) -> Histogram {
+        let range = (max - min).max(0.0);
+        let bin_width = if bin_width > 0.0 {
+            bin_width.max(range / MAX_BINS as f32)
+        } else {
+            range / MAX_BINS as f32
+        }
+        .max(f32::EPSILON);
+        let bins = ((range / bin_width).ceil().max(1.0) as usize).min(MAX_BINS);
+        let mut counts = vec![0; bins];
+        for v in values {
+            if v.is_nan() || v < min || v > max {
+                continue;
+            }
+            counts[(((v - min) / bin_width) as usize).min(bins - 1)] += 1;
+        }
+        Histogram {
+            min,
+            bin_width,
+            counts,
+        }
+    }
+
+    pub fn max(&self) -> f32 {
+        self.min + self.bin_width * self.counts.len() as f32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_caps_bins() {
+        let h = Histogram::new([10.0, 20.0, 30.0], 10.0, 30.0, 0.0);
+        assert_eq!(h.counts.len(), MAX_BINS);
+        assert_eq!(h.counts.iter().sum::<usize>(), 3);
+
+        let h = Histogram::new([10.0, 30.0], 10.0, 30.0, f32::NAN);
+        assert!(h.counts.len() <= MAX_BINS);
+
+        let h = Histogram::new([10.0, 30.0], 10.0, 30.0, -1.0);
+        assert!(h.counts.len() <= MAX_BINS);
+    }
+
+    #[test]
+    fn histogram_bins_values() {
+        let h = Histogram::new([10.0, 10.5, 12.0, 14.0, 9.0, f32::NAN], 10.0, 14.0, 1.0);
+        assert_eq!(h.counts, vec![2, 0, 1, 1]);
+        assert_eq!(h.max(), 14.0);
+    }
+}